@@ -0,0 +1,284 @@
+use chess_lib::{Game, Move, Position};
+
+use crate::controller::{move_hints, MoveHint};
+use crate::notation::{check_suffix, san};
+use crate::pieces::piece_letter;
+
+struct Node {
+    game: Game,
+    // The move that led here and how it is written, none for the starting position.
+    mv: Option<Move>,
+    san: String,
+    parent: Option<usize>,
+    // The first child continues the main line, the others are side lines.
+    children: Vec<usize>,
+}
+
+// A board where either side can move, keeping every line tried as a tree of
+// positions. Moving somewhere new from the middle of a line starts a side line.
+pub struct AnalysisBoard {
+    nodes: Vec<Node>,
+    current: usize,
+    selected: Option<(usize, Vec<MoveHint>)>,
+}
+
+fn same_move(a: &Move, b: &Move) -> bool{
+    return a.from.idx == b.from.idx && a.to.idx == b.to.idx && a.promotion_choice.map(piece_letter) == b.promotion_choice.map(piece_letter);
+}
+
+impl AnalysisBoard {
+    pub fn new(start: Game) -> AnalysisBoard{
+        let root = Node { game: start, mv: None, san: String::new(), parent: None, children: Vec::new() };
+        return AnalysisBoard { nodes: vec![root], current: 0, selected: None };
+    }
+
+    // Replays a finished game as the main line and starts at its last position.
+    pub fn from_game(start: Game, moves: &[Move]) -> AnalysisBoard{
+        let mut analysis = AnalysisBoard::new(start);
+        for mv in moves {
+            if !analysis.play(*mv) {
+                break;
+            }
+        }
+        return analysis;
+    }
+
+    pub fn game(&self) -> &Game{
+        return &self.nodes[self.current].game;
+    }
+
+    pub fn last_move(&self) -> Option<(usize, usize)>{
+        return self.nodes[self.current].mv.map(|mv| (mv.from.idx, mv.to.idx));
+    }
+
+    pub fn selected(&self) -> Option<(usize, &[MoveHint])>{
+        return self.selected.as_ref().map(|(from, targets)| (*from, targets.as_slice()));
+    }
+
+    // Plays `mv` from the current position, following the existing line if it was tried before.
+    pub fn play(&mut self, mv: Move) -> bool{
+        self.selected = None;
+        let existing = self.nodes[self.current].children.iter().copied()
+            .find(|child| self.nodes[*child].mv.is_some_and(|played| same_move(&played, &mv)));
+        if let Some(child) = existing {
+            self.current = child;
+            return true;
+        }
+        let mut game = self.game().clone();
+        let mut written = san(&game, &mv);
+        if game.make_move(mv).is_err() {
+            return false;
+        }
+        written.push_str(check_suffix(&game));
+        let index = self.nodes.len();
+        self.nodes.push(Node { game, mv: Some(mv), san: written, parent: Some(self.current), children: Vec::new() });
+        self.nodes[self.current].children.push(index);
+        self.current = index;
+        return true;
+    }
+
+    // Selects a piece of the side to move, or moves the selected one. A move
+    // that still needs a promotion piece is handed back instead of played.
+    pub fn square_clicked(&mut self, index: usize) -> Option<Move>{
+        if let Some((from, targets)) = self.selected.take() {
+            if from == index {
+                return None;
+            }
+            if targets.iter().any(|hint| hint.square == index) {
+                let mv = Move::new(self.game(), Position::new_from_idx(from).ok()?, Position::new_from_idx(index).ok()?).ok()?;
+                if mv.is_promotion() {
+                    return Some(mv);
+                }
+                self.play(mv);
+                return None;
+            }
+        }
+        let game = self.game();
+        if game.get_board()[index].is_some_and(|piece| piece.colour == game.get_active_colour()) {
+            self.selected = move_hints(game, index).map(|targets| (index, targets));
+        }
+        return None;
+    }
+
+    pub fn back(&mut self){
+        self.selected = None;
+        if let Some(parent) = self.nodes[self.current].parent {
+            self.current = parent;
+        }
+    }
+
+    pub fn forward(&mut self){
+        self.selected = None;
+        if let Some(child) = self.nodes[self.current].children.first() {
+            self.current = *child;
+        }
+    }
+
+    pub fn to_start(&mut self){
+        self.selected = None;
+        self.current = 0;
+    }
+
+    pub fn to_end(&mut self){
+        self.selected = None;
+        while let Some(child) = self.nodes[self.current].children.first() {
+            self.current = *child;
+        }
+    }
+
+    // Switches to the next (or previous, with -1) alternative to the move that led here.
+    pub fn switch_variation(&mut self, step: i32){
+        self.selected = None;
+        let Some(parent) = self.nodes[self.current].parent else {
+            return;
+        };
+        let siblings = &self.nodes[parent].children;
+        let position = siblings.iter().position(|child| *child == self.current).unwrap_or(0) as i32;
+        self.current = siblings[(position + step).rem_euclid(siblings.len() as i32) as usize];
+    }
+
+    // Makes the line leading to the current position the main line all the way from the start.
+    pub fn promote_variation(&mut self){
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            let children = &mut self.nodes[parent].children;
+            children.retain(|child| *child != node);
+            children.insert(0, node);
+            node = parent;
+        }
+    }
+
+    pub fn is_main_line(&self) -> bool{
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            if self.nodes[parent].children.first() != Some(&node) {
+                return false;
+            }
+            node = parent;
+        }
+        return true;
+    }
+
+    // The moves from the start to the current position, one numbered row per move pair.
+    pub fn line(&self) -> Vec<String>{
+        let mut path = Vec::new();
+        let mut node = self.current;
+        while let Some(parent) = self.nodes[node].parent {
+            path.push(node);
+            node = parent;
+        }
+        path.reverse();
+        let black_starts = self.nodes[0].game.get_active_colour().is_black();
+        let mut rows: Vec<String> = Vec::new();
+        for (ply, node) in path.iter().enumerate() {
+            let ply = ply + black_starts as usize;
+            let san = &self.nodes[*node].san;
+            match (ply % 2, rows.last_mut()) {
+                (1, Some(row)) => { row.push(' '); row.push_str(san); },
+                (1, None) => rows.push(format!("{}... {}", ply / 2 + 1, san)),
+                _ => rows.push(format!("{}. {}", ply / 2 + 1, san)),
+            }
+        }
+        return rows;
+    }
+
+    // Every move tried from the current position, the main line first.
+    pub fn continuations(&self) -> Vec<String>{
+        return self.nodes[self.current].children.iter().map(|child| self.nodes[*child].san.clone()).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{parse_move, ParsedMove};
+    use crate::test_support::square;
+
+    fn play(analysis: &mut AnalysisBoard, text: &str) {
+        match parse_move(analysis.game(), text) {
+            ParsedMove::Legal(mv) => assert!(analysis.play(mv)),
+            _ => panic!("{} is not legal here", text),
+        }
+    }
+
+    fn analysis_after(moves: &[&str]) -> AnalysisBoard {
+        let mut analysis = AnalysisBoard::new(Game::new());
+        for text in moves {
+            play(&mut analysis, text);
+        }
+        analysis
+    }
+
+    #[test]
+    fn main_line_is_numbered_by_move() {
+        let analysis = analysis_after(&["e4", "e5", "Nf3"]);
+        assert_eq!(analysis.line(), vec!["1. e4 e5", "2. Nf3"]);
+        assert!(analysis.is_main_line());
+    }
+
+    #[test]
+    fn new_moves_in_the_middle_start_side_lines() {
+        let mut analysis = analysis_after(&["e4", "e5", "Nf3"]);
+        analysis.back();
+        analysis.back();
+        play(&mut analysis, "c5");
+        assert!(!analysis.is_main_line());
+        assert_eq!(analysis.line(), vec!["1. e4 c5"]);
+        analysis.back();
+        assert_eq!(analysis.continuations(), vec!["e5", "c5"]);
+        // Playing a move that was tried before follows it instead of adding another line.
+        play(&mut analysis, "e5");
+        analysis.back();
+        assert_eq!(analysis.continuations().len(), 2);
+        analysis.forward();
+        analysis.forward();
+        assert_eq!(analysis.last_move(), Some((square("g1"), square("f3"))));
+    }
+
+    #[test]
+    fn variations_can_be_switched_and_promoted() {
+        let mut analysis = analysis_after(&["e4", "e5"]);
+        analysis.back();
+        play(&mut analysis, "c5");
+        play(&mut analysis, "Nf3");
+        analysis.back();
+        analysis.switch_variation(1);
+        assert_eq!(analysis.line(), vec!["1. e4 e5"]);
+        analysis.switch_variation(-1);
+        assert_eq!(analysis.line(), vec!["1. e4 c5"]);
+        analysis.forward();
+        analysis.promote_variation();
+        assert!(analysis.is_main_line());
+        analysis.to_start();
+        analysis.to_end();
+        assert_eq!(analysis.line(), vec!["1. e4 c5", "2. Nf3"]);
+    }
+
+    #[test]
+    fn finished_games_are_replayed() {
+        let moves: Vec<Move> = {
+            let mut game = Game::new();
+            ["f3", "e5", "g4", "Qh4"].iter().map(|text| {
+                let ParsedMove::Legal(mv) = parse_move(&game, text) else { panic!() };
+                game.make_move(mv).unwrap();
+                mv
+            }).collect()
+        };
+        let analysis = AnalysisBoard::from_game(Game::new(), &moves);
+        assert_eq!(analysis.line(), vec!["1. f3 e5", "2. g4 Qh4#"]);
+    }
+
+    #[test]
+    fn clicks_move_either_side() {
+        let mut analysis = AnalysisBoard::new(Game::new());
+        // Black can't move first.
+        assert!(analysis.square_clicked(square("e7")).is_none());
+        assert!(analysis.selected().is_none());
+        analysis.square_clicked(square("e2"));
+        assert_eq!(analysis.selected().map(|(from, targets)| (from, targets.len())), Some((square("e2"), 2)));
+        analysis.square_clicked(square("e4"));
+        analysis.square_clicked(square("e7"));
+        analysis.square_clicked(square("e5"));
+        assert_eq!(analysis.line(), vec!["1. e4 e5"]);
+    }
+}
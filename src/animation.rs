@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use chess_lib::Piece;
+
+pub const DEFAULT_DURATION: Duration = Duration::from_millis(200);
+
+// A piece travelling between two board indices.
+#[derive(Clone, Copy)]
+pub struct Slide {
+    pub piece: Piece,
+    pub from: usize,
+    pub to: usize,
+}
+
+// Pieces sliding from the board before a move to the board after it. Castling
+// moves the rook too, and a captured piece (the pawn next to the destination
+// for en passant) stays visible until the capturing piece arrives.
+pub struct Animation {
+    pub slides: Vec<Slide>,
+    pub captured: Option<(Piece, usize)>,
+    elapsed: Duration,
+    duration: Duration,
+}
+
+impl Animation {
+    pub fn new(before: &[Option<Piece>; 64], from: usize, to: usize, duration: Duration) -> Option<Animation>{
+        let piece = before[from]?;
+        let mut slides = vec![Slide { piece, from, to }];
+        let mut captured = before[to].map(|captured| (captured, to));
+        let row_start = from - from % 8;
+        match piece.to_char() {
+            'K' if (from % 8).abs_diff(to % 8) == 2 => {
+                let (rook_from, rook_to) = if to > from { (row_start + 7, to - 1) } else { (row_start, to + 1) };
+                if let Some(rook) = before[rook_from] {
+                    slides.push(Slide { piece: rook, from: rook_from, to: rook_to });
+                }
+            },
+            'P' if from % 8 != to % 8 && before[to].is_none() => {
+                let passed = row_start + to % 8;
+                captured = before[passed].map(|pawn| (pawn, passed));
+            },
+            _ => {},
+        }
+        return Some(Animation { slides, captured, elapsed: Duration::ZERO, duration });
+    }
+
+    pub fn advance(&mut self, delta: Duration){
+        self.elapsed = (self.elapsed + delta).min(self.duration);
+    }
+
+    pub fn is_finished(&self) -> bool{
+        return self.elapsed >= self.duration;
+    }
+
+    // How far along the pieces are, 0 to 1, easing out towards the end.
+    pub fn progress(&self) -> f32{
+        if self.duration.is_zero() {
+            return 1.;
+        }
+        let t = self.elapsed.as_secs_f32() / self.duration.as_secs_f32();
+        return 1. - (1. - t) * (1. - t);
+    }
+
+    // Squares whose piece on the current board is drawn by the animation instead.
+    pub fn covers(&self, index: usize) -> bool{
+        return self.slides.iter().any(|slide| slide.to == index);
+    }
+
+    // Top left corner of a sliding piece, in board pixels with `square_size` pixels per square.
+    pub fn position(&self, slide: &Slide, square_size: f32) -> (f32, f32){
+        let t = self.progress();
+        let (from_x, from_y) = ((slide.from % 8) as f32, (slide.from / 8) as f32);
+        let (to_x, to_y) = ((slide.to % 8) as f32, (slide.to / 8) as f32);
+        return ((from_x + (to_x - from_x) * t) * square_size, (from_y + (to_y - from_y) * t) * square_size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{board_after, square};
+
+    #[test]
+    fn quiet_move_slides_one_piece() {
+        let mut animation = Animation::new(&board_after(&[]), square("g1"), square("f3"), Duration::from_millis(100)).unwrap();
+        assert_eq!(animation.slides.len(), 1);
+        assert!(animation.captured.is_none());
+        assert!(animation.covers(square("f3")));
+        assert_eq!(animation.position(&animation.slides[0], 90.), (6. * 90., 7. * 90.));
+        animation.advance(Duration::from_millis(60));
+        assert!(!animation.is_finished());
+        animation.advance(Duration::from_millis(60));
+        assert!(animation.is_finished());
+        assert_eq!(animation.position(&animation.slides[0], 90.), (5. * 90., 5. * 90.));
+    }
+
+    #[test]
+    fn castling_slides_the_rook_too() {
+        let before = board_after(&[("e2", "e4"), ("e7", "e5"), ("g1", "f3"), ("b8", "c6"), ("f1", "c4"), ("f8", "c5")]);
+        let animation = Animation::new(&before, square("e1"), square("g1"), DEFAULT_DURATION).unwrap();
+        assert_eq!(animation.slides.len(), 2);
+        assert_eq!((animation.slides[1].from, animation.slides[1].to), (square("h1"), square("f1")));
+        assert!(animation.covers(square("f1")));
+    }
+
+    #[test]
+    fn en_passant_keeps_the_passed_pawn_until_the_end() {
+        let before = board_after(&[("e2", "e4"), ("a7", "a6"), ("e4", "e5"), ("d7", "d5")]);
+        let animation = Animation::new(&before, square("e5"), square("d6"), DEFAULT_DURATION).unwrap();
+        assert_eq!(animation.captured.map(|(_, square)| square), Some(square("d5")));
+    }
+
+    #[test]
+    fn captures_keep_the_captured_piece() {
+        let before = board_after(&[("e2", "e4"), ("d7", "d5")]);
+        let animation = Animation::new(&before, square("e4"), square("d5"), DEFAULT_DURATION).unwrap();
+        assert_eq!(animation.captured.map(|(_, square)| square), Some(square("d5")));
+    }
+
+    #[test]
+    fn zero_duration_is_already_done() {
+        let animation = Animation::new(&board_after(&[]), square("e2"), square("e4"), Duration::ZERO).unwrap();
+        assert!(animation.is_finished());
+        assert_eq!(animation.progress(), 1.);
+    }
+}
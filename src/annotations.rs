@@ -0,0 +1,136 @@
+use crate::notation::square_name;
+
+// The four colours online boards use, with their PGN letters.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum MarkColour {
+    Green,
+    Red,
+    Yellow,
+    Blue,
+}
+
+impl MarkColour {
+    // Plain right-click is green, shift red, alt blue and both (or ctrl) yellow.
+    pub fn from_modifiers(shift: bool, alt: bool, ctrl: bool) -> MarkColour{
+        return match (shift, alt || ctrl) {
+            (false, false) => MarkColour::Green,
+            (true, false) => MarkColour::Red,
+            (false, true) if !ctrl => MarkColour::Blue,
+            _ => MarkColour::Yellow,
+        };
+    }
+
+    fn letter(&self) -> char{
+        return match self {
+            MarkColour::Green => 'G',
+            MarkColour::Red => 'R',
+            MarkColour::Yellow => 'Y',
+            MarkColour::Blue => 'B',
+        };
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Arrow {
+    pub from: usize,
+    pub to: usize,
+    pub colour: MarkColour,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SquareMark {
+    pub square: usize,
+    pub colour: MarkColour,
+}
+
+// Arrows and circled squares drawn with the right mouse button.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct Annotations {
+    pub arrows: Vec<Arrow>,
+    pub marks: Vec<SquareMark>,
+}
+
+impl Annotations {
+    pub fn new() -> Annotations{
+        return Annotations::default();
+    }
+
+    pub fn is_empty(&self) -> bool{
+        return self.arrows.is_empty() && self.marks.is_empty();
+    }
+
+    pub fn clear(&mut self){
+        self.arrows.clear();
+        self.marks.clear();
+    }
+
+    // Drawing the same arrow again in the same colour removes it, in another colour recolours it.
+    pub fn toggle_arrow(&mut self, from: usize, to: usize, colour: MarkColour){
+        match self.arrows.iter().position(|arrow| arrow.from == from && arrow.to == to) {
+            Some(i) if self.arrows[i].colour == colour => { self.arrows.remove(i); },
+            Some(i) => self.arrows[i].colour = colour,
+            None => self.arrows.push(Arrow { from, to, colour }),
+        }
+    }
+
+    pub fn toggle_mark(&mut self, square: usize, colour: MarkColour){
+        match self.marks.iter().position(|mark| mark.square == square) {
+            Some(i) if self.marks[i].colour == colour => { self.marks.remove(i); },
+            Some(i) => self.marks[i].colour = colour,
+            None => self.marks.push(SquareMark { square, colour }),
+        }
+    }
+
+    // The `[%csl ...][%cal ...]` commands that go inside a PGN comment, empty if there is nothing to export.
+    pub fn to_pgn(&self) -> String{
+        let mut text = String::new();
+        if !self.marks.is_empty() {
+            let marks: Vec<String> = self.marks.iter().map(|mark| format!("{}{}", mark.colour.letter(), square_name(mark.square))).collect();
+            text.push_str(&format!("[%csl {}]", marks.join(",")));
+        }
+        if !self.arrows.is_empty() {
+            let arrows: Vec<String> = self.arrows.iter().map(|arrow| format!("{}{}{}", arrow.colour.letter(), square_name(arrow.from), square_name(arrow.to))).collect();
+            text.push_str(&format!("[%cal {}]", arrows.join(",")));
+        }
+        return text;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::square;
+
+    #[test]
+    fn modifiers_pick_the_colour() {
+        assert_eq!(MarkColour::from_modifiers(false, false, false), MarkColour::Green);
+        assert_eq!(MarkColour::from_modifiers(true, false, false), MarkColour::Red);
+        assert_eq!(MarkColour::from_modifiers(false, true, false), MarkColour::Blue);
+        assert_eq!(MarkColour::from_modifiers(true, true, false), MarkColour::Yellow);
+        assert_eq!(MarkColour::from_modifiers(false, false, true), MarkColour::Yellow);
+    }
+
+    #[test]
+    fn drawing_twice_removes_or_recolours() {
+        let mut annotations = Annotations::new();
+        annotations.toggle_arrow(square("e2"), square("e4"), MarkColour::Green);
+        annotations.toggle_arrow(square("e2"), square("e4"), MarkColour::Red);
+        assert_eq!(annotations.arrows, vec![Arrow { from: square("e2"), to: square("e4"), colour: MarkColour::Red }]);
+        annotations.toggle_arrow(square("e2"), square("e4"), MarkColour::Red);
+        assert!(annotations.arrows.is_empty());
+        annotations.toggle_mark(square("d5"), MarkColour::Blue);
+        assert!(!annotations.is_empty());
+        annotations.toggle_mark(square("d5"), MarkColour::Blue);
+        assert!(annotations.is_empty());
+    }
+
+    #[test]
+    fn exports_pgn_commands() {
+        let mut annotations = Annotations::new();
+        assert_eq!(annotations.to_pgn(), "");
+        annotations.toggle_mark(square("d4"), MarkColour::Green);
+        annotations.toggle_mark(square("e5"), MarkColour::Red);
+        annotations.toggle_arrow(square("g1"), square("f3"), MarkColour::Yellow);
+        assert_eq!(annotations.to_pgn(), "[%csl Gd4,Re5][%cal Yg1f3]");
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{Deserialize, Serialize};
+
+// Longer messages are cut short, both the ones we send and the ones we get.
+pub const MAX_MESSAGE_CHARS: usize = 200;
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ChatLine {
+    pub from_us: bool,
+    pub text: String,
+}
+
+// The messages of one game, the one being typed and what hasn't been read yet.
+pub struct ChatLog {
+    lines: Vec<ChatLine>,
+    pub draft: String,
+    open: bool,
+    unread: usize,
+    // Messages from a muted opponent are dropped as they arrive.
+    muted: bool,
+}
+
+fn shortened(text: &str) -> String{
+    return text.trim().chars().take(MAX_MESSAGE_CHARS).collect();
+}
+
+impl ChatLog {
+    pub fn new() -> ChatLog{
+        return ChatLog::with_lines(Vec::new());
+    }
+
+    // A log carrying on from earlier messages, like those of a resumed game.
+    pub fn with_lines(lines: Vec<ChatLine>) -> ChatLog{
+        return ChatLog { lines, draft: String::new(), open: false, unread: 0, muted: false };
+    }
+
+    pub fn lines(&self) -> &[ChatLine]{
+        return &self.lines;
+    }
+
+    pub fn is_open(&self) -> bool{
+        return self.open;
+    }
+
+    // Opening the log counts everything in it as read.
+    pub fn toggle_open(&mut self){
+        self.open = !self.open;
+        self.unread = 0;
+    }
+
+    pub fn unread(&self) -> usize{
+        return self.unread;
+    }
+
+    pub fn is_muted(&self) -> bool{
+        return self.muted;
+    }
+
+    pub fn toggle_mute(&mut self){
+        self.muted = !self.muted;
+    }
+
+    pub fn received(&mut self, text: &str){
+        let text = shortened(text);
+        if self.muted || text.is_empty() {
+            return;
+        }
+        self.lines.push(ChatLine { from_us: false, text });
+        if !self.open {
+            self.unread += 1;
+        }
+    }
+
+    pub fn type_char(&mut self, character: char){
+        if !character.is_control() && self.draft.chars().count() < MAX_MESSAGE_CHARS {
+            self.draft.push(character);
+        }
+    }
+
+    // Logs the draft and hands it back to be sent, unless there is nothing to send.
+    pub fn send(&mut self) -> Option<String>{
+        let text = shortened(&self.draft);
+        self.draft.clear();
+        if text.is_empty() {
+            return None;
+        }
+        self.lines.push(ChatLine { from_us: true, text: text.clone() });
+        return Some(text);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_unread_until_the_log_is_opened() {
+        let mut chat = ChatLog::new();
+        chat.received("hello");
+        chat.received("  ");
+        assert_eq!(chat.unread(), 1);
+        chat.toggle_open();
+        assert_eq!(chat.unread(), 0);
+        chat.received("still there?");
+        assert_eq!(chat.unread(), 0);
+        assert_eq!(chat.lines().len(), 2);
+    }
+
+    #[test]
+    fn drafts_are_sent_trimmed() {
+        let mut chat = ChatLog::new();
+        for character in " gg \n".chars() {
+            chat.type_char(character);
+        }
+        assert_eq!(chat.send(), Some(String::from("gg")));
+        assert_eq!(chat.send(), None);
+        assert_eq!(chat.lines(), [ChatLine { from_us: true, text: String::from("gg") }]);
+    }
+
+    #[test]
+    fn muted_opponents_are_not_logged() {
+        let mut chat = ChatLog::new();
+        chat.toggle_mute();
+        chat.received("spam");
+        assert!(chat.lines().is_empty());
+        assert_eq!(chat.unread(), 0);
+        chat.toggle_mute();
+        chat.received(&"x".repeat(500));
+        assert_eq!(chat.lines()[0].text.len(), MAX_MESSAGE_CHARS);
+    }
+}
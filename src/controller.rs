@@ -0,0 +1,992 @@
+use chess_lib::{Colour, Game, GameOverReason, GameState, Move, Piece, PieceType, Position};
+use chess_networking::Ack;
+
+use crate::fen::{same_position, Setup};
+use crate::network::{chess_lib_state_to_network_state, to_chess_lib_move};
+
+// What the player did, already translated from window coordinates.
+pub enum Input {
+    SquareClicked(usize),
+    // A whole move typed in, see `notation::parse_move`.
+    MoveTyped(Move),
+    PromotionChosen(PieceType),
+    DrawRequested,
+    DrawOfferAnswered(bool),
+    PremovesCancelled,
+    TakebackRequested,
+    TakebackAnswered(bool),
+}
+
+// Something the caller has to tell the opponent.
+pub enum Command {
+    SendMove(Move),
+    // A move carrying our draw offer, the opponent answers it in the ack.
+    OfferDraw(Move),
+    // The ack held back for the move the draw was offered with.
+    AnswerDrawOffer(Ack),
+    // Both sides count plies from the start of the game, a takeback goes back to one of those counts.
+    RequestTakeback(u16),
+    AnswerTakeback { takeback_to: u16, accepted: bool },
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum EndReason {
+    // Checkmate, stalemate etc, ask the board for the details.
+    Board,
+    DrawAgreed,
+    OpponentForfeited,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HintKind {
+    Quiet,
+    Capture,
+    EnPassant,
+    Castle,
+}
+
+// A legal target square for the selected piece.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct MoveHint {
+    pub square: usize,
+    pub kind: HintKind,
+}
+
+// A move queued during the opponent's turn, checked and sent once it is ours.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Premove {
+    pub from: usize,
+    pub to: usize,
+}
+
+// How our game compares to the peer's after reconnecting, see `network::Resync`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResyncOutcome {
+    // Same position. A move of ours still waiting for its ack never arrived and has to be sent again.
+    InSync,
+    // The peer played the move we were waiting on an ack for, only the ack was lost.
+    OurMoveArrived,
+    // We played the peer's last move but it never got our ack, it catches up on its side.
+    PeerBehind,
+    Diverged,
+}
+
+pub enum UiState {
+    Connecting,
+    Idle,
+    PieceSelected { from: usize, targets: Vec<MoveHint> },
+    AwaitingPromotion { mv: Move },
+    WaitingForOpponent,
+    // Picking where a piece goes in a premove, any square is allowed.
+    PremoveSelected { from: usize },
+    // The opponent offered a draw along with their last move, it is not acked until we answer.
+    DrawOffered,
+    // The opponent asked to go back to `takeback_to` plies, nothing moves until we answer.
+    TakebackOffered { takeback_to: u16 },
+    GameOver { reason: EndReason },
+}
+
+// The king of the side to move, if it is in check or has been mated.
+pub fn checked_king(game: &Game) -> Option<usize>{
+    let in_check = match game.get_game_state() {
+        GameState::Check => true,
+        GameState::GameOver => matches!(game.get_game_over_reason(), Some(GameOverReason::Checkmate)),
+        GameState::Active => false,
+    };
+    if !in_check {
+        return None;
+    }
+    let active = game.get_active_colour();
+    return game.get_board().iter().position(|piece| match piece {
+        Some(piece) => piece.to_char() == 'K' && piece.colour == active,
+        None => false,
+    });
+}
+
+// Legal target squares of the piece on `from`.
+pub fn move_hints(game: &Game, from: usize) -> Option<Vec<MoveHint>>{
+    let moves = game.get_legal_moves_from(Position::new_from_idx(from).ok()?).ok()?;
+    let board = game.get_board();
+    return Some(moves.iter().map(|mv| MoveHint { square: mv.to.idx, kind: hint_kind(&board, from, mv.to.idx) }).collect());
+}
+
+fn hint_kind(board: &[Option<Piece>; 64], from: usize, to: usize) -> HintKind{
+    let Some(piece) = board[from] else {
+        return HintKind::Quiet;
+    };
+    let file_distance = (from % 8).abs_diff(to % 8);
+    if board[to].is_some() {
+        return HintKind::Capture;
+    }
+    return match piece.to_char() {
+        'K' if file_distance == 2 => HintKind::Castle,
+        // A pawn moving diagonally onto an empty square.
+        'P' if file_distance == 1 => HintKind::EnPassant,
+        _ => HintKind::Quiet,
+    };
+}
+
+// Game logic behind the window: selection, move hints, promotion and whose turn it is.
+// It never touches the network, anything the opponent needs to know is handed back as a `Command`.
+pub struct GameController {
+    board: Game,
+    // What `board` was set up from, the game itself doesn't know its castling rights or en passant square.
+    start: Setup,
+    player_side: Colour,
+    state: UiState,
+    // Board indices of every move played by either side, and the board before each of them for takebacks.
+    played: Vec<Move>,
+    history: Vec<Game>,
+    // A takeback we asked for and have no answer to yet.
+    pending_takeback: Option<u16>,
+    // A draw we offered, it goes out with our next move.
+    offering_draw: bool,
+    premoves: Vec<Premove>,
+}
+
+impl GameController {
+    pub fn new(board: Game, start: Setup) -> GameController{
+        return GameController {
+            board: board,
+            start: start,
+            player_side: Colour::White,
+            state: UiState::Connecting,
+            played: Vec::new(),
+            history: Vec::new(),
+            pending_takeback: None,
+            offering_draw: false,
+            premoves: Vec::new(),
+        };
+    }
+
+    // Called once the handshake is done and we know which side we play.
+    pub fn connected(&mut self, player_side: Colour){
+        self.player_side = player_side;
+        self.state = self.resting_state();
+    }
+
+    pub fn board(&self) -> &Game{
+        return &self.board;
+    }
+
+    pub fn player_side(&self) -> Colour{
+        return self.player_side;
+    }
+
+    pub fn state(&self) -> &UiState{
+        return &self.state;
+    }
+
+    pub fn last_move(&self) -> Option<(usize, usize)>{
+        return self.played.last().map(|mv| (mv.from.idx, mv.to.idx));
+    }
+
+    // Number of moves played by both sides together.
+    pub fn ply(&self) -> usize{
+        return self.played.len();
+    }
+
+    // The position the game started from.
+    pub fn start(&self) -> &Game{
+        return self.history.first().unwrap_or(&self.board);
+    }
+
+    pub fn start_fen(&self) -> String{
+        return self.start.to_fen();
+    }
+
+    // FEN of the position now, played forward from the start.
+    pub fn fen(&self) -> String{
+        let mut setup = self.start.clone();
+        for mv in self.played.iter() {
+            setup.play(mv);
+        }
+        return setup.to_fen();
+    }
+
+    // The position the game started from and every move since, for analysing it afterwards.
+    pub fn record(&self) -> (Game, Vec<Move>){
+        return (self.start().clone(), self.played.clone());
+    }
+
+    pub fn pending_takeback(&self) -> Option<u16>{
+        return self.pending_takeback;
+    }
+
+    pub fn offering_draw(&self) -> bool{
+        return self.offering_draw;
+    }
+
+    pub fn checked_king(&self) -> Option<usize>{
+        return checked_king(&self.board);
+    }
+
+    pub fn premoves(&self) -> &[Premove]{
+        return &self.premoves;
+    }
+
+    pub fn is_players_turn(&self) -> bool{
+        return self.board.get_active_colour() == self.player_side;
+    }
+
+    // The state to fall back to when nothing is in progress.
+    fn resting_state(&self) -> UiState{
+        if self.board.get_game_state() == GameState::GameOver {
+            return UiState::GameOver { reason: EndReason::Board };
+        }
+        if self.is_players_turn() {
+            return UiState::Idle;
+        }
+        return UiState::WaitingForOpponent;
+    }
+
+    pub fn handle_input(&mut self, input: Input) -> Option<Command>{
+        let state = std::mem::replace(&mut self.state, UiState::Connecting);
+        let (next, command) = match (state, input) {
+            // Our pieces stay put while the opponent decides on our takeback.
+            (state @ (UiState::Idle | UiState::PieceSelected { .. }), Input::SquareClicked(_) | Input::MoveTyped(_)) if self.pending_takeback.is_some() => (state, None),
+            (UiState::Idle, Input::SquareClicked(index)) => (self.select_piece(index).unwrap_or(UiState::Idle), None),
+            (UiState::PieceSelected { from, targets }, Input::SquareClicked(index)) => self.square_clicked(from, &targets, index),
+            (UiState::Idle | UiState::PieceSelected { .. }, Input::MoveTyped(mv)) => self.move_typed(mv),
+            // Nothing changes until the opponent answers the move the offer goes out with.
+            (state @ (UiState::Idle | UiState::PieceSelected { .. }), Input::DrawRequested) => {
+                self.offering_draw = true;
+                (state, None)
+            },
+            (UiState::AwaitingPromotion { mut mv }, Input::PromotionChosen(piece)) => {
+                mv.promotion_choice = Some(piece);
+                (UiState::WaitingForOpponent, Some(self.send(mv)))
+            },
+            (UiState::DrawOffered, Input::DrawOfferAnswered(accepted)) => {
+                let (next, end_state) = match accepted {
+                    true => (UiState::GameOver { reason: EndReason::DrawAgreed }, Some(chess_networking::GameState::Draw)),
+                    false => (self.resting_state(), chess_lib_state_to_network_state(&self.board)),
+                };
+                (next, Some(Command::AnswerDrawOffer(Ack { ok: true, end_state })))
+            },
+            (UiState::WaitingForOpponent, Input::SquareClicked(index)) => (self.select_premove(index).unwrap_or(UiState::WaitingForOpponent), None),
+            (UiState::PremoveSelected { from }, Input::SquareClicked(index)) => (self.premove_clicked(from, index), None),
+            (state, Input::TakebackRequested) if self.can_request_takeback() => {
+                let takeback_to = self.takeback_target().unwrap_or_default();
+                self.pending_takeback = Some(takeback_to);
+                (state, Some(Command::RequestTakeback(takeback_to)))
+            },
+            (UiState::TakebackOffered { takeback_to }, Input::TakebackAnswered(accepted)) => {
+                if accepted {
+                    self.take_back(takeback_to);
+                }
+                (self.resting_state(), Some(Command::AnswerTakeback { takeback_to, accepted }))
+            },
+            (state, Input::PremovesCancelled) => {
+                self.premoves.clear();
+                let next = match state {
+                    UiState::PremoveSelected { .. } => UiState::WaitingForOpponent,
+                    state => state,
+                };
+                (next, None)
+            },
+            (state, _) => (state, None),
+        };
+        self.state = next;
+        return command;
+    }
+
+    fn square_clicked(&mut self, from: usize, targets: &[MoveHint], index: usize) -> (UiState, Option<Command>){
+        if !targets.iter().any(|hint| hint.square == index) {
+            // Clicking another of our own pieces selects it instead.
+            return (self.select_piece(index).unwrap_or(UiState::Idle), None);
+        }
+        let mv = match (Position::new_from_idx(from), Position::new_from_idx(index)) {
+            (Ok(from), Ok(to)) => Move::new(&self.board, from, to),
+            _ => return (UiState::Idle, None),
+        };
+        return match mv {
+            Ok(mv) if mv.is_promotion() => (UiState::AwaitingPromotion { mv }, None),
+            Ok(mv) => (UiState::WaitingForOpponent, Some(self.send(mv))),
+            Err(_) => (UiState::Idle, None),
+        };
+    }
+
+    fn move_typed(&mut self, mv: Move) -> (UiState, Option<Command>){
+        if !self.board.get_board()[mv.from.idx].is_some_and(|piece| piece.colour == self.player_side) {
+            return (UiState::Idle, None);
+        }
+        if mv.is_promotion() && mv.promotion_choice.is_none() {
+            return (UiState::AwaitingPromotion { mv }, None);
+        }
+        return (UiState::WaitingForOpponent, Some(self.send(mv)));
+    }
+
+    fn send(&self, mv: Move) -> Command{
+        if self.offering_draw {
+            return Command::OfferDraw(mv);
+        }
+        return Command::SendMove(mv);
+    }
+
+    fn select_piece(&self, index: usize) -> Option<UiState>{
+        let piece = self.board.get_board()[index]?;
+        if piece.colour != self.player_side {
+            return None;
+        }
+        return Some(UiState::PieceSelected { from: index, targets: move_hints(&self.board, index)? });
+    }
+
+    // The board as it will look once the queued premoves are played, ignoring what the opponent does.
+    fn premove_board(&self) -> [Option<Piece>; 64]{
+        let mut board = self.board.get_board();
+        for premove in self.premoves.iter() {
+            board[premove.to] = board[premove.from].take();
+        }
+        return board;
+    }
+
+    fn select_premove(&self, index: usize) -> Option<UiState>{
+        let piece = self.premove_board()[index]?;
+        if piece.colour != self.player_side || self.board.get_game_state() == GameState::GameOver {
+            return None;
+        }
+        return Some(UiState::PremoveSelected { from: index });
+    }
+
+    fn premove_clicked(&mut self, from: usize, index: usize) -> UiState{
+        if index == from {
+            return UiState::WaitingForOpponent;
+        }
+        if let Some(selected) = self.select_premove(index) {
+            return selected;
+        }
+        self.premoves.push(Premove { from, to: index });
+        return UiState::WaitingForOpponent;
+    }
+
+    // Going back to before our last move, and the opponent's reply if they already made it.
+    fn takeback_target(&self) -> Option<u16>{
+        let plies = if self.is_players_turn() { 2 } else { 1 };
+        return self.ply().checked_sub(plies).map(|ply| ply as u16);
+    }
+
+    // Neither while our own move is on its way, nor with anything else waiting for an answer.
+    fn takeback_allowed(&self) -> bool{
+        return match self.state {
+            UiState::Idle | UiState::PieceSelected { .. } => true,
+            UiState::WaitingForOpponent | UiState::PremoveSelected { .. } => !self.is_players_turn(),
+            _ => false,
+        };
+    }
+
+    pub fn can_request_takeback(&self) -> bool{
+        return self.pending_takeback.is_none() && self.takeback_allowed() && self.takeback_target().is_some();
+    }
+
+    // The opponent asks to go back to `takeback_to` plies. Returns false if that
+    // can't be answered right now, or would undo more than their last move and our reply.
+    pub fn takeback_requested(&mut self, takeback_to: u16) -> bool{
+        let undone = self.ply().checked_sub(takeback_to as usize);
+        // One ply is only their own move, when it is our turn.
+        let sensible = match undone {
+            Some(1) => self.is_players_turn(),
+            Some(2) => true,
+            _ => false,
+        };
+        if !sensible || !self.takeback_allowed() {
+            return false;
+        }
+        self.state = UiState::TakebackOffered { takeback_to };
+        return true;
+    }
+
+    // The answer to our own request, anything we didn't ask for is ignored.
+    pub fn takeback_answered(&mut self, takeback_to: u16, accepted: bool){
+        if self.pending_takeback != Some(takeback_to) {
+            return;
+        }
+        self.pending_takeback = None;
+        if accepted {
+            self.take_back(takeback_to);
+            self.state = self.resting_state();
+        }
+    }
+
+    fn take_back(&mut self, takeback_to: u16){
+        let ply = takeback_to as usize;
+        if ply >= self.history.len() {
+            return;
+        }
+        self.board = self.history[ply].clone();
+        self.history.truncate(ply);
+        self.played.truncate(ply);
+        self.premoves.clear();
+    }
+
+    // Once it is our turn the first premove is played if it is still legal, pawns
+    // reaching the last rank become queens. An illegal premove drops the whole
+    // queue, since the ones after it were planned around it.
+    pub fn take_premove(&mut self) -> Option<Command>{
+        if !matches!(self.state, UiState::Idle) || self.premoves.is_empty() || self.pending_takeback.is_some() {
+            return None;
+        }
+        let premove = self.premoves.remove(0);
+        let legal = Position::new_from_idx(premove.from).ok()
+            .and_then(|from| self.board.get_legal_moves_from(from).ok())
+            .and_then(|moves| moves.into_iter().find(|mv| mv.to.idx == premove.to));
+        let Some(mut mv) = legal else {
+            self.premoves.clear();
+            return None;
+        };
+        if mv.is_promotion() {
+            mv.promotion_choice = Some(PieceType::Queen);
+        }
+        self.state = UiState::WaitingForOpponent;
+        return Some(self.send(mv));
+    }
+
+    // The opponent's ack to a move from `Command::SendMove` or `Command::OfferDraw`.
+    // A draw offer stays up for the next move if the move itself was rejected.
+    pub fn move_acked(&mut self, mv: Move, ack: &Ack){
+        if !ack.ok {
+            self.local_move_rejected();
+            return;
+        }
+        let offered = std::mem::take(&mut self.offering_draw);
+        self.apply_local_move(mv);
+        if offered && matches!(ack.end_state, Some(chess_networking::GameState::Draw)) && !matches!(self.state, UiState::GameOver { .. }) {
+            self.state = UiState::GameOver { reason: EndReason::DrawAgreed };
+        }
+    }
+
+    // Plays a move from `Command::SendMove` once the opponent accepted it.
+    pub fn apply_local_move(&mut self, mv: Move) -> bool{
+        let ok = self.play(mv);
+        self.state = self.resting_state();
+        return ok;
+    }
+
+    fn play(&mut self, mv: Move) -> bool{
+        let before = self.board.clone();
+        if self.board.make_move(mv).is_err() {
+            return false;
+        }
+        self.history.push(before);
+        self.played.push(mv);
+        return true;
+    }
+
+    // Checks the peer's position and move count against ours, `pending` being a move we sent that wasn't acked.
+    pub fn compare_with_peer(&self, pending: Option<Move>, fen: &str, ply: usize) -> ResyncOutcome{
+        let matches = |game: &Game| same_position(fen, &Setup::from_game(game).to_fen());
+        if ply == self.ply() && matches(&self.board) {
+            return ResyncOutcome::InSync;
+        }
+        if let Some(mv) = pending.filter(|_| ply == self.ply() + 1) {
+            let mut after = self.board.clone();
+            if after.make_move(mv).is_ok() && matches(&after) {
+                return ResyncOutcome::OurMoveArrived;
+            }
+        }
+        if ply + 1 == self.ply() && self.history.last().is_some_and(matches) {
+            return ResyncOutcome::PeerBehind;
+        }
+        return ResyncOutcome::Diverged;
+    }
+
+    // Plays the moves of a saved game before it is resumed, stopping at the first illegal one.
+    pub fn replay(&mut self, moves: &[Move]) -> bool{
+        return moves.iter().all(|mv| self.play(*mv));
+    }
+
+    // The opponent did not accept our move, let the player try again.
+    pub fn local_move_rejected(&mut self){
+        self.state = self.resting_state();
+    }
+
+    // Returns whether the move was legal. If it came with a draw offer the
+    // caller must hold the ack until the player has answered it.
+    pub fn apply_opponent_move(&mut self, mv: &chess_networking::Move) -> bool{
+        if mv.forfeit {
+            self.state = UiState::GameOver { reason: EndReason::OpponentForfeited };
+            self.premoves.clear();
+            return true;
+        }
+        let ok = match to_chess_lib_move(&self.board, mv) {
+            Some(mv) => self.play(mv),
+            None => false,
+        };
+        self.state = self.resting_state();
+        if ok && mv.offer_draw && matches!(self.state, UiState::Idle) {
+            self.state = UiState::DrawOffered;
+        }
+        return ok;
+    }
+
+    // Plays the opponent's move and returns the ack for it, none while a draw offered with it waits for an answer.
+    pub fn ack_opponent_move(&mut self, mv: &chess_networking::Move) -> Option<Ack>{
+        let ok = self.apply_opponent_move(mv);
+        let end_state = match self.state {
+            UiState::DrawOffered => return None,
+            UiState::GameOver { reason: EndReason::OpponentForfeited } => Some(chess_networking::GameState::Resign),
+            _ => chess_lib_state_to_network_state(&self.board),
+        };
+        return Some(Ack { ok, end_state });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{parse_move, ParsedMove};
+    use crate::test_support::square;
+
+    fn network_move(from: &str, to: &str) -> chess_networking::Move {
+        let from = Position::new_from_idx(square(from)).unwrap();
+        let to = Position::new_from_idx(square(to)).unwrap();
+        chess_networking::Move {
+            from: (from.file as u8, from.rank as u8),
+            to: (to.file as u8, to.rank as u8),
+            forfeit: false,
+            offer_draw: false,
+            promotion: None,
+        }
+    }
+
+    fn play(game: &mut Game, from: &str, to: &str) {
+        let mv = Move::new(game, Position::new_from_idx(square(from)).unwrap(), Position::new_from_idx(square(to)).unwrap()).unwrap();
+        game.make_move(mv).unwrap();
+    }
+
+    fn controller(game: Game, side: Colour) -> GameController {
+        // None of these games start from a position where the guessed rights matter.
+        let start = Setup::from_game(&game);
+        let mut controller = GameController::new(game, start);
+        controller.connected(side);
+        controller
+    }
+
+    fn click(controller: &mut GameController, name: &str) -> Option<Command> {
+        controller.handle_input(Input::SquareClicked(square(name)))
+    }
+
+    fn sent_move(command: Option<Command>) -> Move {
+        match command {
+            Some(Command::SendMove(mv)) => mv,
+            _ => panic!("expected a move to send"),
+        }
+    }
+
+    #[test]
+    fn starts_connecting_and_ignores_input() {
+        let mut controller = GameController::new(Game::new(), Setup::initial());
+        assert!(matches!(controller.state(), UiState::Connecting));
+        assert!(click(&mut controller, "e2").is_none());
+        assert!(matches!(controller.state(), UiState::Connecting));
+    }
+
+    #[test]
+    fn selecting_a_piece_highlights_its_moves() {
+        let mut controller = controller(Game::new(), Colour::White);
+        assert!(click(&mut controller, "e2").is_none());
+        let mut targets = hints(&controller);
+        targets.sort_by_key(|hint| hint.square);
+        assert_eq!(targets, vec![
+            MoveHint { square: square("e4"), kind: HintKind::Quiet },
+            MoveHint { square: square("e3"), kind: HintKind::Quiet },
+        ]);
+    }
+
+    fn hints(controller: &GameController) -> Vec<MoveHint> {
+        match controller.state() {
+            UiState::PieceSelected { targets, .. } => targets.clone(),
+            _ => panic!("expected a selection"),
+        }
+    }
+
+    fn hint_at(controller: &GameController, name: &str) -> HintKind {
+        hints(controller).iter().find(|hint| hint.square == square(name)).expect("no hint on that square").kind
+    }
+
+    #[test]
+    fn hints_tell_captures_apart() {
+        let mut game = Game::new();
+        for (from, to) in [("e2", "e4"), ("d7", "d5")] {
+            play(&mut game, from, to);
+        }
+        let mut controller = controller(game, Colour::White);
+        click(&mut controller, "e4");
+        assert_eq!(hint_at(&controller, "d5"), HintKind::Capture);
+        assert_eq!(hint_at(&controller, "e5"), HintKind::Quiet);
+    }
+
+    #[test]
+    fn hints_mark_en_passant() {
+        let mut game = Game::new();
+        for (from, to) in [("e2", "e4"), ("a7", "a6"), ("e4", "e5"), ("d7", "d5")] {
+            play(&mut game, from, to);
+        }
+        let mut controller = controller(game, Colour::White);
+        click(&mut controller, "e5");
+        assert_eq!(hint_at(&controller, "d6"), HintKind::EnPassant);
+        assert_eq!(hint_at(&controller, "e6"), HintKind::Quiet);
+    }
+
+    #[test]
+    fn hints_mark_castling() {
+        let mut game = Game::new();
+        for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3"), ("b8", "c6"), ("f1", "c4"), ("f8", "c5")] {
+            play(&mut game, from, to);
+        }
+        let mut controller = controller(game, Colour::White);
+        click(&mut controller, "e1");
+        assert_eq!(hint_at(&controller, "g1"), HintKind::Castle);
+        assert_eq!(hint_at(&controller, "f1"), HintKind::Quiet);
+    }
+
+    #[test]
+    fn clicking_a_highlighted_square_submits_the_move() {
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "g1");
+        let mv = sent_move(click(&mut controller, "f3"));
+        assert_eq!(mv.from.idx, square("g1"));
+        assert_eq!(mv.to.idx, square("f3"));
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        // Nothing is played until the opponent has accepted it.
+        assert!(controller.board().get_board()[square("g1")].is_some());
+        assert!(controller.apply_local_move(mv));
+        assert!(controller.board().get_board()[square("f3")].is_some());
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+    }
+
+    #[test]
+    fn rejected_move_returns_to_idle() {
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "e2");
+        sent_move(click(&mut controller, "e4"));
+        controller.local_move_rejected();
+        assert!(matches!(controller.state(), UiState::Idle));
+        assert!(controller.board().get_board()[square("e2")].is_some());
+    }
+
+    #[test]
+    fn clicking_elsewhere_clears_the_selection() {
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "e2");
+        assert!(click(&mut controller, "e5").is_none());
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn clicking_another_own_piece_switches_the_selection() {
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "e2");
+        click(&mut controller, "d2");
+        assert!(matches!(controller.state(), UiState::PieceSelected { from, .. } if *from == square("d2")));
+    }
+
+    #[test]
+    fn opponent_pieces_and_empty_squares_cannot_be_selected() {
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "e7");
+        assert!(matches!(controller.state(), UiState::Idle));
+        click(&mut controller, "e4");
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn nothing_is_sent_on_the_opponents_turn() {
+        let mut controller = controller(Game::new(), Colour::Black);
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        // Only starts picking a premove.
+        assert!(click(&mut controller, "e7").is_none());
+        assert!(matches!(controller.state(), UiState::PremoveSelected { .. }));
+        assert!(controller.apply_opponent_move(&network_move("e2", "e4")));
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn promotion_waits_for_a_choice() {
+        let mut game = Game::new();
+        for (from, to) in [("h2", "h4"), ("g7", "g5"), ("h4", "g5"), ("h7", "h6"), ("g5", "h6"), ("f8", "g7"), ("h6", "g7"), ("g8", "f6")] {
+            play(&mut game, from, to);
+        }
+        let mut controller = controller(game, Colour::White);
+        click(&mut controller, "g7");
+        assert!(click(&mut controller, "h8").is_none());
+        assert!(matches!(controller.state(), UiState::AwaitingPromotion { .. }));
+        // Board clicks do nothing until a piece is picked.
+        assert!(click(&mut controller, "a2").is_none());
+        let mv = sent_move(controller.handle_input(Input::PromotionChosen(PieceType::Rook)));
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        assert!(matches!(mv.promotion_choice, Some(PieceType::Rook)));
+        assert_eq!(mv.to.idx, square("h8"));
+    }
+
+    #[test]
+    fn draw_offer_waits_for_an_answer() {
+        let mut controller = controller(Game::new(), Colour::Black);
+        let mut offer = network_move("e2", "e4");
+        offer.offer_draw = true;
+        assert!(controller.ack_opponent_move(&offer).is_none());
+        assert!(matches!(controller.state(), UiState::DrawOffered));
+        assert!(click(&mut controller, "e7").is_none());
+        assert!(matches!(controller.handle_input(Input::DrawOfferAnswered(false)), Some(Command::AnswerDrawOffer(Ack { ok: true, end_state: None }))));
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn accepted_draw_ends_the_game() {
+        let mut controller = controller(Game::new(), Colour::Black);
+        let mut offer = network_move("e2", "e4");
+        offer.offer_draw = true;
+        controller.apply_opponent_move(&offer);
+        assert!(matches!(controller.handle_input(Input::DrawOfferAnswered(true)), Some(Command::AnswerDrawOffer(Ack { ok: true, end_state: Some(chess_networking::GameState::Draw) }))));
+        assert!(matches!(controller.state(), UiState::GameOver { reason: EndReason::DrawAgreed }));
+    }
+
+    #[test]
+    fn draw_offer_goes_out_with_our_next_move() {
+        let mut controller = controller(Game::new(), Colour::White);
+        assert!(controller.handle_input(Input::DrawRequested).is_none());
+        assert!(matches!(controller.state(), UiState::Idle));
+        click(&mut controller, "e2");
+        let mv = match click(&mut controller, "e4") {
+            Some(Command::OfferDraw(mv)) => mv,
+            _ => panic!("expected the move to carry the offer"),
+        };
+        controller.move_acked(mv, &Ack { ok: true, end_state: Some(chess_networking::GameState::Draw) });
+        assert!(matches!(controller.state(), UiState::GameOver { reason: EndReason::DrawAgreed }));
+    }
+
+    #[test]
+    fn declined_draw_offer_is_not_sent_again() {
+        let mut controller = controller(Game::new(), Colour::White);
+        controller.handle_input(Input::DrawRequested);
+        click(&mut controller, "e2");
+        let Some(Command::OfferDraw(mv)) = click(&mut controller, "e4") else {
+            panic!("expected the move to carry the offer");
+        };
+        controller.move_acked(mv, &Ack { ok: true, end_state: None });
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        assert!(!controller.offering_draw());
+        controller.apply_opponent_move(&network_move("e7", "e5"));
+        click(&mut controller, "d2");
+        assert!(matches!(click(&mut controller, "d4"), Some(Command::SendMove(_))));
+    }
+
+    #[test]
+    fn opponent_forfeit_ends_the_game() {
+        let mut controller = controller(Game::new(), Colour::Black);
+        let mut forfeit = network_move("e2", "e4");
+        forfeit.forfeit = true;
+        assert!(matches!(controller.ack_opponent_move(&forfeit), Some(Ack { ok: true, end_state: Some(chess_networking::GameState::Resign) })));
+        assert!(matches!(controller.state(), UiState::GameOver { reason: EndReason::OpponentForfeited }));
+    }
+
+    #[test]
+    fn last_move_is_tracked_for_both_sides() {
+        let mut controller = controller(Game::new(), Colour::White);
+        assert!(controller.last_move().is_none());
+        click(&mut controller, "e2");
+        let mv = sent_move(click(&mut controller, "e4"));
+        // Not played yet, so nothing to show.
+        assert!(controller.last_move().is_none());
+        controller.apply_local_move(mv);
+        assert_eq!(controller.last_move(), Some((square("e2"), square("e4"))));
+        controller.apply_opponent_move(&network_move("e7", "e5"));
+        assert_eq!(controller.last_move(), Some((square("e7"), square("e5"))));
+        // A rejected move leaves it alone.
+        controller.apply_opponent_move(&network_move("a2", "a5"));
+        assert_eq!(controller.last_move(), Some((square("e7"), square("e5"))));
+    }
+
+    #[test]
+    fn checked_king_is_found() {
+        let mut game = Game::new();
+        for (from, to) in [("e2", "e4"), ("f7", "f6"), ("d2", "d4")] {
+            play(&mut game, from, to);
+        }
+        let mut controller = controller(game, Colour::White);
+        assert!(controller.checked_king().is_none());
+        controller.apply_opponent_move(&network_move("g7", "g5"));
+        assert!(controller.checked_king().is_none());
+        click(&mut controller, "d1");
+        let mv = sent_move(click(&mut controller, "h5"));
+        controller.apply_local_move(mv);
+        assert_eq!(controller.checked_king(), Some(square("e8")));
+    }
+
+    #[test]
+    fn opponent_moves_are_validated() {
+        let mut controller = controller(Game::new(), Colour::Black);
+        assert!(!controller.apply_opponent_move(&network_move("e2", "e6")));
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+    }
+
+    #[test]
+    fn typed_moves_are_sent_like_clicked_ones() {
+        let mut controller = controller(Game::new(), Colour::White);
+        let ParsedMove::Legal(mv) = parse_move(controller.board(), "Nf3") else {
+            panic!("Nf3 should be legal");
+        };
+        let sent = sent_move(controller.handle_input(Input::MoveTyped(mv)));
+        assert_eq!((sent.from.idx, sent.to.idx), (square("g1"), square("f3")));
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        // Not our turn any more.
+        assert!(controller.handle_input(Input::MoveTyped(mv)).is_none());
+    }
+
+    fn premove(controller: &mut GameController, from: &str, to: &str) {
+        assert!(click(controller, from).is_none());
+        assert!(matches!(controller.state(), UiState::PremoveSelected { .. }));
+        assert!(click(controller, to).is_none());
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+    }
+
+    fn waiting_after_e4() -> GameController {
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "e2");
+        let mv = sent_move(click(&mut controller, "e4"));
+        controller.apply_local_move(mv);
+        controller
+    }
+
+    #[test]
+    fn premoves_are_sent_when_the_opponent_has_moved() {
+        let mut controller = waiting_after_e4();
+        premove(&mut controller, "g1", "f3");
+        // Planned from where the knight will be.
+        premove(&mut controller, "f3", "g5");
+        assert_eq!(controller.premoves(), &[Premove { from: square("g1"), to: square("f3") }, Premove { from: square("f3"), to: square("g5") }]);
+        assert!(controller.take_premove().is_none());
+        controller.apply_opponent_move(&network_move("e7", "e5"));
+        let mv = sent_move(controller.take_premove());
+        assert_eq!((mv.from.idx, mv.to.idx), (square("g1"), square("f3")));
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        controller.apply_local_move(mv);
+        assert_eq!(controller.premoves().len(), 1);
+    }
+
+    #[test]
+    fn illegal_premoves_drop_the_queue() {
+        let mut controller = waiting_after_e4();
+        premove(&mut controller, "e4", "e5");
+        premove(&mut controller, "d2", "d4");
+        controller.apply_opponent_move(&network_move("e7", "e5"));
+        assert!(controller.take_premove().is_none());
+        assert!(controller.premoves().is_empty());
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn premoves_can_be_cancelled() {
+        let mut controller = waiting_after_e4();
+        premove(&mut controller, "g1", "f3");
+        click(&mut controller, "b1");
+        controller.handle_input(Input::PremovesCancelled);
+        assert!(controller.premoves().is_empty());
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        // Opponent pieces can't be premoved.
+        assert!(click(&mut controller, "e7").is_none());
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+    }
+
+    fn opponent_asks(controller: &mut GameController, takeback_to: u16) -> Option<Command> {
+        assert!(controller.takeback_requested(takeback_to));
+        assert!(matches!(controller.state(), UiState::TakebackOffered { .. }));
+        controller.handle_input(Input::TakebackAnswered(true))
+    }
+
+    #[test]
+    fn takeback_of_our_move_before_the_reply() {
+        let mut controller = waiting_after_e4();
+        assert_eq!(controller.ply(), 1);
+        assert!(matches!(controller.handle_input(Input::TakebackRequested), Some(Command::RequestTakeback(0))));
+        // Only one request at a time.
+        assert!(controller.handle_input(Input::TakebackRequested).is_none());
+        controller.takeback_answered(0, true);
+        assert_eq!(controller.ply(), 0);
+        assert!(controller.last_move().is_none());
+        assert!(controller.board().get_board()[square("e2")].is_some());
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn takeback_after_the_reply_undoes_both_moves() {
+        let mut controller = waiting_after_e4();
+        controller.apply_opponent_move(&network_move("e7", "e5"));
+        assert!(matches!(controller.handle_input(Input::TakebackRequested), Some(Command::RequestTakeback(0))));
+        controller.takeback_answered(0, false);
+        assert_eq!(controller.ply(), 2);
+        assert_eq!(controller.pending_takeback(), None);
+        assert!(matches!(controller.state(), UiState::Idle));
+    }
+
+    #[test]
+    fn answering_a_takeback_rolls_back_our_board() {
+        let mut controller = controller(Game::new(), Colour::Black);
+        controller.apply_opponent_move(&network_move("e2", "e4"));
+        // Nothing of theirs to take back but their own move, on our turn.
+        assert!(!controller.can_request_takeback());
+        assert!(matches!(opponent_asks(&mut controller, 0), Some(Command::AnswerTakeback { takeback_to: 0, accepted: true })));
+        assert_eq!(controller.ply(), 0);
+        assert!(matches!(controller.state(), UiState::WaitingForOpponent));
+        // Going back further than their last move is refused.
+        controller.apply_opponent_move(&network_move("d2", "d4"));
+        click(&mut controller, "d7");
+        let mv = sent_move(click(&mut controller, "d5"));
+        controller.apply_local_move(mv);
+        controller.apply_opponent_move(&network_move("c2", "c4"));
+        assert!(!controller.takeback_requested(0));
+        assert!(controller.takeback_requested(2));
+    }
+
+    #[test]
+    fn replayed_games_resume_on_the_right_turn() {
+        let mut game = Game::new();
+        let mut moves = Vec::new();
+        for (from, to) in [("e2", "e4"), ("e7", "e5"), ("g1", "f3")] {
+            let mv = Move::new(&game, Position::new_from_idx(square(from)).unwrap(), Position::new_from_idx(square(to)).unwrap()).unwrap();
+            game.make_move(mv).unwrap();
+            moves.push(mv);
+        }
+        let mut controller = GameController::new(Game::new(), Setup::initial());
+        assert!(controller.replay(&moves));
+        controller.connected(Colour::Black);
+        assert_eq!(controller.ply(), 3);
+        assert_eq!(controller.last_move(), Some((square("g1"), square("f3"))));
+        assert!(matches!(controller.state(), UiState::Idle));
+        // Replaying them again from the new position fails on the first one.
+        assert!(!controller.replay(&moves));
+    }
+
+    #[test]
+    fn resync_spots_the_move_lost_in_the_drop() {
+        let fen_of = |game: &Game| Setup::from_game(game).to_fen();
+        let mut controller = controller(Game::new(), Colour::White);
+        click(&mut controller, "e2");
+        let mv = sent_move(click(&mut controller, "e4"));
+        let mut peer = Game::new();
+        // The move never got there.
+        assert_eq!(controller.compare_with_peer(Some(mv), &fen_of(&peer), 0), ResyncOutcome::InSync);
+        // It got there but the ack didn't get back.
+        peer.make_move(mv).unwrap();
+        assert_eq!(controller.compare_with_peer(Some(mv), &fen_of(&peer), 1), ResyncOutcome::OurMoveArrived);
+        controller.apply_local_move(mv);
+        assert_eq!(controller.compare_with_peer(None, &fen_of(&peer), 1), ResyncOutcome::InSync);
+        // Their reply arrived here but our ack didn't get back.
+        let before_reply = peer.clone();
+        controller.apply_opponent_move(&network_move("e7", "e5"));
+        assert_eq!(controller.compare_with_peer(None, &fen_of(&before_reply), 1), ResyncOutcome::PeerBehind);
+        assert_eq!(controller.compare_with_peer(None, &fen_of(&Game::new()), 0), ResyncOutcome::Diverged);
+    }
+
+    #[test]
+    fn fen_keeps_the_rights_the_game_started_with() {
+        let start = Setup::parse("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1").unwrap();
+        let mut controller = GameController::new(start.to_game().unwrap(), start);
+        controller.connected(Colour::White);
+        assert_eq!(controller.start_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1");
+        click(&mut controller, "a1");
+        let mv = sent_move(click(&mut controller, "a2"));
+        controller.apply_local_move(mv);
+        assert_eq!(controller.fen(), "r3k2r/8/8/8/8/8/R7/4K2R b - - 1 1");
+    }
+}
@@ -1,7 +1,20 @@
 use std::vec;
 
-use chess_lib::GameOverReason;
-use ggez::{glam::{vec2, Vec2}, graphics::{self, Color, PxScale, Rect, TextFragment}, Context, GameError};
+use chess_lib::{GameOverReason, PieceType};
+use ggez::{glam::{vec2, Vec2}, graphics::{self, Color, DrawParam, PxScale, Rect, TextFragment}, Context, GameError};
+
+use caspervk_chess_gui::analysis::AnalysisBoard;
+use caspervk_chess_gui::animation::Animation;
+use caspervk_chess_gui::annotations::{Annotations, MarkColour};
+use caspervk_chess_gui::chat::ChatLog;
+use caspervk_chess_gui::material::Material;
+use caspervk_chess_gui::network::LinkStatus;
+use caspervk_chess_gui::notation::{square_name, ParsedMove};
+use caspervk_chess_gui::editor::{PositionEditor, PALETTE};
+use caspervk_chess_gui::pieces::{piece_from_letter, piece_letter, piece_type_from_char};
+use caspervk_chess_gui::controller::{EndReason, HintKind, MoveHint, Premove};
+use caspervk_chess_gui::lobby::{Lobby, LobbyField, LobbyMode, LobbyStatus};
+use caspervk_chess_gui::theme::{Theme, ThemeColor};
 
 use crate::PieceImages;
 
@@ -10,11 +23,16 @@ pub enum Direction {
     Vertical,
     Horizontal
 }
-pub fn draw_repeated_elements(canvas: &mut graphics::Canvas, elements: [&str; 8], direction: Direction){
+pub fn theme_color(color: ThemeColor) -> Color{
+    let [r, g, b, a] = color.0;
+    return Color::from_rgba(r, g, b, a);
+}
+
+pub fn draw_repeated_elements(canvas: &mut graphics::Canvas, elements: [&str; 8], direction: Direction, theme: &Theme){
    for i in 0..8{
-       let mut color = Color::from_rgb(118,150,86);
+       let mut color = theme_color(theme.dark_square);
        if i % 2 == 0{
-           color = Color::WHITE;
+           color = theme_color(theme.light_square);
        }
        let mut drawParam = ggez::glam::Vec2::new((i*90+70) as f32, 690.0);
        if direction == Direction::Vertical{
@@ -23,77 +41,78 @@ pub fn draw_repeated_elements(canvas: &mut graphics::Canvas, elements: [&str; 8]
        canvas.draw(
            &graphics::Text::new(TextFragment{
                text: elements[i].into(),
-               font: Some("LiberationMono".into()),
+               font: Some(theme.coordinate_font.clone()),
                color: Some(color),
                scale: Some(PxScale::from(32.0)),
            }),
            drawParam);
    }
 }
-pub fn draw_board_indexing(canvas: &mut graphics::Canvas, ctx: &Context) -> Result<(), GameError>{
+pub fn draw_board_indexing(canvas: &mut graphics::Canvas, ctx: &Context, theme: &Theme) -> Result<(), GameError>{
    Ok({
-       draw_repeated_elements(canvas, ["a","b","c","d","e","f","g","h"], Direction::Horizontal);
-       draw_repeated_elements(canvas, ["8","7","6","5","4","3","2","1"], Direction::Vertical);
+       draw_repeated_elements(canvas, ["a","b","c","d","e","f","g","h"], Direction::Horizontal, theme);
+       draw_repeated_elements(canvas, ["8","7","6","5","4","3","2","1"], Direction::Vertical, theme);
       }
      )
 }
 
-pub fn draw_board_pieces(canvas: &mut graphics::Canvas, board: [Option<chess_lib::Piece>; 8*8], piece_images: &PieceImages){
+// Width the piece images are drawn at, centred a little high in their 90 pixel square.
+pub const PIECE_SIZE: f32 = 60.;
+const PIECE_OFFSET: f32 = 13.;
+
+// Draws a piece `size` pixels wide, or just its letter when the set has no image for it.
+pub fn draw_piece(canvas: &mut graphics::Canvas, piece_images: &PieceImages, colour: chess_lib::Colour, piece_type: PieceType, dest: Vec2, size: f32){
+    match piece_images.get(colour, piece_type) {
+        Some(sprite) => {
+            let scale = size / sprite.width;
+            canvas.draw(&sprite.image, DrawParam::new().src(sprite.src).dest(dest).scale(Vec2::new(scale, scale)));
+        },
+        None => {
+            let letter = piece_letter(piece_type);
+            let letter = if colour.is_black() { letter.to_ascii_lowercase() } else { letter };
+            canvas.draw(
+                &graphics::Text::new(TextFragment{
+                    text: letter.to_string(),
+                    font: Some("LiberationMono".into()),
+                    color: Some(Color::BLACK),
+                    scale: Some(PxScale::from(size)),
+                }), dest);
+        },
+    }
+}
+
+fn draw_board_piece(canvas: &mut graphics::Canvas, piece: &chess_lib::Piece, piece_images: &PieceImages, x: f32, y: f32){
+    draw_piece(canvas, piece_images, piece.colour, piece.piece_type, vec2(x + PIECE_OFFSET, y + PIECE_OFFSET), PIECE_SIZE);
+}
+
+// While a move is animating the pieces it moves are drawn by the animation, on top of the rest.
+pub fn draw_board_pieces(canvas: &mut graphics::Canvas, board: [Option<chess_lib::Piece>; 8*8], piece_images: &PieceImages, animation: Option<&Animation>){
    for i in 0..8{
        for j in 0..8{
-           if(board[i*8+j].is_some()){
+           if(board[i*8+j].is_some() && !animation.is_some_and(|animation| animation.covers(i*8+j))){
                let piece = board[i*8+j].unwrap();
-               let drawParam = vec2((j*90+13) as f32, (i*90+13) as f32);
-               let image_to_draw = match piece.to_char() {
-                   'R' => {
-                       match piece.colour{
-                           chess_lib::Colour::Black => &piece_images.black_rook,
-                           chess_lib::Colour::White => &piece_images.white_rook,
-                       }
-                   },
-                   'N' =>                         
-                       match piece.colour{
-                           chess_lib::Colour::Black => &piece_images.black_knight,
-                           chess_lib::Colour::White => &&piece_images.white_knight,
-                       },
-                   'B' => {
-                       match piece.colour{
-                           chess_lib::Colour::Black => &piece_images.black_bishop,
-                           chess_lib::Colour::White => &piece_images.white_bishop,
-                       }
-                   },
-                   'K' => {
-                       match piece.colour{
-                           chess_lib::Colour::Black => &piece_images.black_king,
-                           chess_lib::Colour::White => &piece_images.white_king,
-                       }
-                   },
-                   'Q' => {
-                       match piece.colour{
-                           chess_lib::Colour::Black => &piece_images.black_queen,
-                           chess_lib::Colour::White => &piece_images.white_queen,
-                       }
-                   },
-                   'P' => {
-                       match piece.colour{
-                           chess_lib::Colour::Black => &piece_images.black_pawn,
-                           chess_lib::Colour::White => &piece_images.white_pawn,
-                       }
-                   },
-                   _ => {panic!();},
-               };
-               canvas.draw(image_to_draw, drawParam);
+               draw_board_piece(canvas, &piece, piece_images, (j*90) as f32, (i*90) as f32);
            }
        }
    }
+   let Some(animation) = animation else {
+       return;
+   };
+   if let Some((piece, index)) = animation.captured {
+       draw_board_piece(canvas, &piece, piece_images, (index%8*90) as f32, (index/8*90) as f32);
+   }
+   for slide in animation.slides.iter(){
+       let (x, y) = animation.position(slide, 90.);
+       draw_board_piece(canvas, &slide.piece, piece_images, x, y);
+   }
 }
 
-pub fn draw_board_rectangles(canvas: &mut graphics::Canvas, ctx: &Context) -> Result<(), GameError>{
+pub fn draw_board_rectangles(canvas: &mut graphics::Canvas, ctx: &Context, theme: &Theme) -> Result<(), GameError>{
     Ok(for i in 0..8{
         for j in 0..8{
-            let mut color = Color::from_rgb(118,150,86);
+            let mut color = theme_color(theme.dark_square);
             if (i + j) % 2 == 0{
-                color = Color::WHITE;
+                color = theme_color(theme.light_square);
             }
             let bounds = Rect::new(0.0, 0.0, 90.0, 90.0);
             let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds , color)?;
@@ -102,34 +121,126 @@ pub fn draw_board_rectangles(canvas: &mut graphics::Canvas, ctx: &Context) -> Re
     })
 }
 
+pub fn draw_last_move(canvas: &mut graphics::Canvas, ctx: &Context, from: usize, to: usize, theme: &Theme) -> Result<(), GameError>{
+    let overlay = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 90.0, 90.0), theme_color(theme.last_move))?;
+    for index in [from, to]{
+        canvas.draw(&overlay, Vec2::new((index % 8 * 90) as f32, (index / 8 * 90) as f32));
+    }
+    Ok(())
+}
+
+// Stacked translucent circles, densest in the middle of the square.
+pub fn draw_check_glow(canvas: &mut graphics::Canvas, ctx: &Context, king: usize, theme: &Theme) -> Result<(), GameError>{
+    let (x, y) = get_index_pos(king as u32);
+    for radius in [44., 36., 28., 20.]{
+        let circle = graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), Vec2::new(0., 0.), radius, 0.2, theme_color(theme.check))?;
+        canvas.draw(&circle, Vec2::new(x as f32, y as f32));
+    }
+    Ok(())
+}
+
 //returns x,y
 fn get_index_pos(index: u32) -> (u32, u32){
     return ((index as u32 % 8)*90+45, index/8*90+45);
 }
 
-pub fn draw_highlighted_squares(canvas: &mut graphics::Canvas, ctx: &Context, to_draw: &Vec<u32>) -> Result<(), GameError>{
+pub fn draw_selected_square(canvas: &mut graphics::Canvas, ctx: &Context, index: usize, theme: &Theme) -> Result<(), GameError>{
+    let overlay = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 90.0, 90.0), theme_color(theme.selected))?;
+    canvas.draw(&overlay, Vec2::new((index % 8 * 90) as f32, (index / 8 * 90) as f32));
+    Ok(())
+}
+
+fn mark_color(colour: MarkColour) -> Color{
+    return match colour {
+        MarkColour::Green => Color::from_rgba(21, 120, 27, 170),
+        MarkColour::Red => Color::from_rgba(136, 32, 32, 170),
+        MarkColour::Yellow => Color::from_rgba(230, 143, 0, 170),
+        MarkColour::Blue => Color::from_rgba(0, 48, 136, 170),
+    };
+}
+
+fn square_centre(index: usize) -> Vec2{
+    return Vec2::new((index % 8 * 90) as f32 + 45., (index / 8 * 90) as f32 + 45.);
+}
+
+// Rings around marked squares and arrows from square centre to square centre, over the pieces.
+pub fn draw_annotations(canvas: &mut graphics::Canvas, ctx: &Context, annotations: &Annotations) -> Result<(), GameError>{
+    for mark in annotations.marks.iter(){
+        let ring = graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(6.), square_centre(mark.square), 41., 0.5, mark_color(mark.colour))?;
+        canvas.draw(&ring, Vec2::new(0., 0.));
+    }
+    for arrow in annotations.arrows.iter(){
+        let (from, to) = (square_centre(arrow.from), square_centre(arrow.to));
+        let direction = (to - from).normalize();
+        let side = Vec2::new(-direction.y, direction.x);
+        // The shaft stops where the head starts so the two don't overlap.
+        let head_base = to - direction * 30.;
+        let shaft = graphics::Mesh::new_line(ctx, &[from + direction * 20., head_base], 14., mark_color(arrow.colour))?;
+        let head = graphics::Mesh::new_polygon(ctx, graphics::DrawMode::fill(), &[to, head_base + side * 20., head_base - side * 20.], mark_color(arrow.colour))?;
+        canvas.draw(&shaft, Vec2::new(0., 0.));
+        canvas.draw(&head, Vec2::new(0., 0.));
+    }
+    Ok(())
+}
+
+// Tints the squares of every queued premove.
+pub fn draw_premoves(canvas: &mut graphics::Canvas, ctx: &Context, premoves: &[Premove], theme: &Theme) -> Result<(), GameError>{
+    let overlay = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 90.0, 90.0), theme_color(theme.premove))?;
+    for premove in premoves{
+        for index in [premove.from, premove.to]{
+            canvas.draw(&overlay, Vec2::new((index % 8 * 90) as f32, (index / 8 * 90) as f32));
+        }
+    }
+    Ok(())
+}
+
+// Outline of the square picked with the arrow keys.
+pub fn draw_cursor(canvas: &mut graphics::Canvas, ctx: &Context, index: usize, theme: &Theme) -> Result<(), GameError>{
+    let [r, g, b, _] = theme.selected.0;
+    let outline = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(4.), Rect::new(2.0, 2.0, 86.0, 86.0), Color::from_rgb(r, g, b))?;
+    canvas.draw(&outline, Vec2::new((index % 8 * 90) as f32, (index / 8 * 90) as f32));
+    Ok(())
+}
+
+// Dots for quiet moves, rings around pieces that can be taken, and their own
+// colours for en passant and castling.
+pub fn draw_highlighted_squares(canvas: &mut graphics::Canvas, ctx: &Context, to_draw: &[MoveHint], theme: &Theme) -> Result<(), GameError>{
     Ok((
-        for index in to_draw{
-            let (x, y) = get_index_pos(*index);
-            let circle = graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), Vec2::new(0., 0.), 15., 0.2, Color::RED)?;
-            canvas.draw(&circle, Vec2::new(x as f32, y as f32))
+        for hint in to_draw{
+            let (x, y) = get_index_pos(hint.square as u32);
+            let mesh = match hint.kind {
+                HintKind::Quiet => graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), Vec2::new(0., 0.), 15., 0.2, theme_color(theme.move_hint))?,
+                HintKind::Capture => graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(6.), Vec2::new(0., 0.), 40., 0.2, theme_color(theme.capture_hint))?,
+                HintKind::EnPassant => graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(6.), Vec2::new(0., 0.), 22., 0.2, theme_color(theme.en_passant_hint))?,
+                HintKind::Castle => graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(6.), Rect::new(-40., -40., 80., 80.), theme_color(theme.castle_hint))?,
+            };
+            canvas.draw(&mesh, Vec2::new(x as f32, y as f32))
         }
     ))
 }
 
-pub fn draw_game_over_window(canvas: &mut graphics::Canvas, ctx: &Context, game_over_reason: GameOverReason, color: chess_lib::Colour){
-    let text = match game_over_reason{
-        GameOverReason::Checkmate => {
-            match color {
-                chess_lib::Colour::White => "White won by checkmate!",
-                chess_lib::Colour::Black => "Black won by checkmate!"
-            }
-        },
-        GameOverReason::FivefoldRepetitionRule => "Draw by five fold repetition",
-        GameOverReason::SeventyFiveMoveRule => "Draw by seventy five move rule",
-        GameOverReason::Stalemate => "Drawby stale mate",
-        _ => {panic!()}
+pub fn game_over_text(board: &chess_lib::Game, reason: EndReason) -> String{
+    let text = match reason{
+        EndReason::DrawAgreed => "Draw by agreement",
+        EndReason::OpponentForfeited => "Opponent forfeited",
+        EndReason::Board => match board.get_game_over_reason(){
+            // The side to move is the one that got mated.
+            Some(GameOverReason::Checkmate) => {
+                match board.get_active_colour() {
+                    chess_lib::Colour::White => "Black won by checkmate!",
+                    chess_lib::Colour::Black => "White won by checkmate!"
+                }
+            },
+            Some(GameOverReason::FivefoldRepetitionRule) => "Draw by five fold repetition",
+            Some(GameOverReason::SeventyFiveMoveRule) => "Draw by seventy five move rule",
+            Some(GameOverReason::Stalemate) => "Draw by stale mate",
+            _ => "Game over"
+        }
     };
+    return String::from(text);
+}
+
+pub fn draw_game_over_window(canvas: &mut graphics::Canvas, ctx: &Context, text: &str){
     let bounds = Rect::new(0.0, 0.0, 300.0, 130.0);
     let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds , Color::RED).unwrap();
     canvas.draw(&rectangle, Vec2::new(220., 260.));
@@ -143,33 +254,329 @@ pub fn draw_game_over_window(canvas: &mut graphics::Canvas, ctx: &Context, game_
 );
 }
 
-pub fn draw_promotion_selection_window(canvas: &mut graphics::Canvas, ctx: &Context, color: chess_lib::Colour, piece_images: &PieceImages){
+// A question from the opponent with Accept and Decline buttons, over the middle of the board.
+pub fn draw_offer_window(canvas: &mut graphics::Canvas, ctx: &Context, question: &str){
     let bounds = Rect::new(0.0, 0.0, 300.0, 130.0);
     let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds , Color::RED).unwrap();
     canvas.draw(&rectangle, Vec2::new(220., 260.));
-    if(color.is_black()){
-        canvas.draw(&piece_images.black_queen, ggez::glam::Vec2::new(255 as f32, 295.0));
-        canvas.draw(&piece_images.black_knight, ggez::glam::Vec2::new(315 as f32, 295.0));
-        canvas.draw(&piece_images.black_rook, ggez::glam::Vec2::new(375 as f32, 295.0));
-        canvas.draw(&piece_images.black_bishop, ggez::glam::Vec2::new(435 as f32, 295.0));
+    canvas.draw(
+        &graphics::Text::new(TextFragment{
+            text: question.to_string(),
+            font: Some("LiberationMono".into()),
+            color: Some(Color::BLACK),
+            scale: Some(PxScale::from(20.0)),
+        }), Vec2::new(240., 280.));
+    for (label, x) in [("Accept", 255.), ("Decline", 385.)]{
+        let button = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 100.0, 40.0), Color::WHITE).unwrap();
+        canvas.draw(&button, Vec2::new(x, 330.));
+        canvas.draw(
+            &graphics::Text::new(TextFragment{
+                text: label.to_string(),
+                font: Some("LiberationMono".into()),
+                color: Some(Color::BLACK),
+                scale: Some(PxScale::from(20.0)),
+            }), Vec2::new(x + 10., 340.));
+    }
+}
+
+// A message over the middle of the board, such as why a pasted FEN couldn't be
+// loaded or that the opponent is gone, with a grey note like "Click to close" under it.
+pub fn draw_message_window(canvas: &mut graphics::Canvas, ctx: &Context, message: &str, note: &str){
+    let lines = wrap(message, 26);
+    let bounds = Rect::new(0.0, 0.0, 340.0, 70.0 + lines.len() as f32 * 24.);
+    let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds, Color::from_rgb(40, 40, 40)).unwrap();
+    canvas.draw(&rectangle, Vec2::new(190., 280.));
+    let outline = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), bounds, Color::RED).unwrap();
+    canvas.draw(&outline, Vec2::new(190., 280.));
+    for (i, line) in lines.iter().enumerate() {
+        draw_label(canvas, line, 205., 295. + i as f32 * 24., Color::WHITE);
+    }
+    draw_label(canvas, note, 205., 305. + lines.len() as f32 * 24., Color::from_rgb(160, 160, 160));
+}
+
+// Name plates sit in the side panel, level with the top and bottom edge of the board.
+pub fn draw_name_plate(canvas: &mut graphics::Canvas, ctx: &Context, name: &str, colour: chess_lib::Colour, to_move: bool, y: f32, theme: &Theme){
+    let background = if to_move { theme_color(theme.accent) } else { Color::from_rgb(40, 40, 40) };
+    let plate = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 170.0, 40.0), background).unwrap();
+    canvas.draw(&plate, Vec2::new(725., y));
+    let swatch = if colour.is_black() { Color::BLACK } else { Color::WHITE };
+    let marker = graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), Vec2::new(0., 0.), 8., 0.2, swatch).unwrap();
+    canvas.draw(&marker, Vec2::new(742., y + 20.));
+    let mut text = String::from(name);
+    if text.chars().count() > 12 {
+        text = text.chars().take(11).collect::<String>() + "…";
     }
-    else{
-        canvas.draw(&piece_images.white_queen, ggez::glam::Vec2::new(255 as f32, 295.0));
-        canvas.draw(&piece_images.white_knight, ggez::glam::Vec2::new(315 as f32, 295.0));
-        canvas.draw(&piece_images.white_rook, ggez::glam::Vec2::new(375 as f32, 295.0));
-        canvas.draw(&piece_images.white_bishop, ggez::glam::Vec2::new(435 as f32, 295.0));
+    canvas.draw(
+        &graphics::Text::new(TextFragment{
+            text: text,
+            font: Some("LiberationMono".into()),
+            color: Some(Color::WHITE),
+            scale: Some(PxScale::from(18.0)),
+        }), Vec2::new(758., y + 11.));
+}
+
+// The typed move under the opponent's name plate, coloured by whether it can be played yet.
+pub fn draw_move_entry(canvas: &mut graphics::Canvas, ctx: &Context, text: &str, parsed: &ParsedMove){
+    let field = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 170.0, 36.0), Color::from_rgb(230, 230, 230)).unwrap();
+    canvas.draw(&field, Vec2::new(725., 60.));
+    let (hint, color) = match parsed {
+        ParsedMove::Empty => ("Type a move", Color::from_rgb(160, 160, 160)),
+        ParsedMove::Legal(_) => ("Enter to play", Color::from_rgb(118, 200, 86)),
+        ParsedMove::Incomplete => ("", Color::WHITE),
+        ParsedMove::Ambiguous => ("Which piece?", Color::from_rgb(255, 170, 0)),
+        ParsedMove::Invalid => ("Not legal", Color::RED),
+    };
+    canvas.draw(
+        &graphics::Text::new(TextFragment{
+            text: format!("{}_", text),
+            font: Some("LiberationMono".into()),
+            color: Some(Color::BLACK),
+            scale: Some(PxScale::from(20.0)),
+        }), Vec2::new(733., 68.));
+    draw_label(canvas, hint, 728., 102., color);
+}
+
+pub fn draw_status_text(canvas: &mut graphics::Canvas, text: &str){
+    canvas.draw(
+        &graphics::Text::new(TextFragment{
+            text: text.to_string(),
+            font: Some("LiberationMono".into()),
+            color: Some(Color::WHITE),
+            scale: Some(PxScale::from(20.0)),
+        }), Vec2::new(745., 250.));
+}
+
+// A coloured dot and a word for the connection, between the move entry and the status text.
+pub fn draw_link_status(canvas: &mut graphics::Canvas, ctx: &Context, status: LinkStatus){
+    let (label, color) = match status {
+        LinkStatus::Connected => ("Connected", Color::from_rgb(118, 200, 86)),
+        LinkStatus::Lagging => ("Lagging", Color::from_rgb(255, 170, 0)),
+        LinkStatus::Lost => ("Connection lost", Color::RED),
+    };
+    let dot = graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), Vec2::new(0., 0.), 6., 0.2, color).unwrap();
+    canvas.draw(&dot, Vec2::new(734., 152.));
+    draw_label(canvas, label, 746., 141., color);
+}
+
+pub fn draw_promotion_selection_window(canvas: &mut graphics::Canvas, ctx: &Context, color: chess_lib::Colour, piece_images: &PieceImages){
+    let bounds = Rect::new(0.0, 0.0, 300.0, 130.0);
+    let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds , Color::RED).unwrap();
+    canvas.draw(&rectangle, Vec2::new(220., 260.));
+    let choices = [PieceType::Queen, PieceType::Knight, PieceType::Rook, PieceType::Bishop];
+    for (i, piece_type) in choices.into_iter().enumerate(){
+        draw_piece(canvas, piece_images, color, piece_type, Vec2::new(255. + i as f32 * 60., 295.), PIECE_SIZE);
     }
 }
 
-pub fn draw_request_draw_button(canvas: &mut graphics::Canvas, ctx: &Context){
+pub fn draw_request_draw_button(canvas: &mut graphics::Canvas, ctx: &Context, label: &str){
     let bounds = Rect::new(0.0, 0.0, 140.0, 50.0);
     let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), bounds , Color::RED).unwrap();
     canvas.draw(&rectangle, Vec2::new(745., 310.));
     canvas.draw(
         &graphics::Text::new(TextFragment{
-            text: "Request draw".to_string(),
+            text: label.to_string(),
             font: Some("LiberationMono".into()),
             color: Some(Color::BLACK),
             scale: Some(PxScale::from(20.0)),
         }), Vec2::new(750., 328.));
-}
\ No newline at end of file
+}
+
+// A button in the side panel below the captured pieces, like the one for takebacks.
+pub fn draw_side_button(canvas: &mut graphics::Canvas, ctx: &Context, label: &str, y: f32){
+    let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 140.0, 40.0), Color::from_rgb(200, 200, 200)).unwrap();
+    canvas.draw(&rectangle, Vec2::new(745., y));
+    // Letters are about 12 pixels wide.
+    draw_label(canvas, label, 745. + (140. - label.len() as f32 * 12.) / 2., y + 10., Color::BLACK);
+}
+
+// The chat log over the side panel below the connection status, newest lines at the
+// bottom, with the mute switch at the top and the message being typed under it.
+pub fn draw_chat(canvas: &mut graphics::Canvas, ctx: &Context, chat: &ChatLog, theme: &Theme){
+    let panel = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 176.0, 490.0), Color::from_rgb(40, 40, 40)).unwrap();
+    canvas.draw(&panel, Vec2::new(722., 170.));
+    draw_label(canvas, "Chat", 728., 178., Color::WHITE);
+    draw_toggle(canvas, ctx, Rect::new(815., 174., 78., 28.), if chat.is_muted() { "Muted" } else { "Mute" }, chat.is_muted(), theme);
+    let mut lines: Vec<(String, Color)> = Vec::new();
+    for line in chat.lines() {
+        let color = if line.from_us { Color::from_rgb(118, 200, 86) } else { Color::WHITE };
+        lines.extend(wrap(&line.text, 14).into_iter().map(|text| (text, color)));
+    }
+    for (i, (text, color)) in lines.iter().skip(lines.len().saturating_sub(17)).enumerate() {
+        draw_label(canvas, text, 728., 214. + i as f32 * 22., *color);
+    }
+    let field = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 170.0, 36.0), Color::from_rgb(230, 230, 230)).unwrap();
+    canvas.draw(&field, Vec2::new(725., 614.));
+    // Only the end of a long message fits.
+    let draft: String = chat.draft.chars().skip(chat.draft.chars().count().saturating_sub(12)).collect();
+    draw_label(canvas, &format!("{}_", draft), 733., 622., Color::BLACK);
+}
+
+// Where the chat opens from, with how many messages came in since it was last looked at.
+pub fn draw_chat_hint(canvas: &mut graphics::Canvas, unread: usize){
+    match unread {
+        0 => draw_label(canvas, "Tab: chat", 728., 620., Color::from_rgb(160, 160, 160)),
+        _ => draw_label(canvas, &format!("Tab: chat ({})", unread), 728., 620., Color::from_rgb(255, 170, 0)),
+    }
+}
+
+// Spectators can't move, the side panel just says so.
+pub fn draw_spectator_panel(canvas: &mut graphics::Canvas){
+    draw_label(canvas, "Spectating", 728., 70., Color::WHITE);
+    draw_label(canvas, "Esc: leave", 728., 610., Color::from_rgb(160, 160, 160));
+}
+
+// The line leading to the shown position, the moves tried from it and the keys, in the side panel.
+pub fn draw_analysis_panel(canvas: &mut graphics::Canvas, analysis: &AnalysisBoard){
+    draw_label(canvas, if analysis.is_main_line() { "Main line" } else { "Side line" }, 728., 130., Color::WHITE);
+    let line = analysis.line();
+    // Only the latest rows fit, the start of a long line scrolls away.
+    for (i, row) in line.iter().skip(line.len().saturating_sub(14)).enumerate() {
+        draw_label(canvas, row, 728., 160. + i as f32 * 22., Color::from_rgb(220, 220, 220));
+    }
+    let continuations = analysis.continuations();
+    if !continuations.is_empty() {
+        draw_label(canvas, &format!("Next: {}", continuations.iter().take(3).cloned().collect::<Vec<_>>().join(" ")), 728., 480., Color::from_rgb(118, 200, 86));
+    }
+    for (i, help) in ["<- ->: step", "Up/Down: line", "M: main line", "Esc: leave"].iter().enumerate() {
+        draw_label(canvas, help, 728., 610. + i as f32 * 22., Color::from_rgb(160, 160, 160));
+    }
+}
+
+// Pieces each side has taken, shrunk down in rows below the draw button, with
+// the material lead next to whoever is ahead. The opponent's captures go on top.
+pub fn draw_captured_pieces(canvas: &mut graphics::Canvas, material: &Material, player_side: chess_lib::Colour, piece_images: &PieceImages){
+    let opponent_side = if player_side.is_black() { chess_lib::Colour::White } else { chess_lib::Colour::Black };
+    for (row, side) in [opponent_side, player_side].iter().enumerate(){
+        let y = 380. + row as f32 * 90.;
+        // The pieces shown are the other side's.
+        let captured_colour = if side.is_black() { chess_lib::Colour::White } else { chess_lib::Colour::Black };
+        let captured = material.captured_by(*side).iter().filter_map(|letter| piece_type_from_char(*letter));
+        for (i, piece_type) in captured.enumerate(){
+            let (x, line) = ((i % 8) as f32 * 18., (i / 8) as f32 * 24.);
+            draw_piece(canvas, piece_images, captured_colour, piece_type, Vec2::new(728. + x, y + line), PIECE_SIZE * 0.35);
+        }
+        let advantage = material.advantage(*side);
+        if advantage > 0 {
+            draw_label(canvas, &format!("+{}", advantage), 728., y + 52., Color::WHITE);
+        }
+    }
+}
+
+fn draw_toggle(canvas: &mut graphics::Canvas, ctx: &Context, bounds: Rect, label: &str, on: bool, theme: &Theme){
+    let color = if on { theme_color(theme.accent) } else { Color::from_rgb(90, 90, 90) };
+    let button = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, bounds.w, bounds.h), color).unwrap();
+    canvas.draw(&button, Vec2::new(bounds.x, bounds.y));
+    draw_label(canvas, label, bounds.x + 8., bounds.y + 6., Color::WHITE);
+}
+
+// Splits `text` into lines of at most `width` characters, breaking between words.
+fn wrap(text: &str, width: usize) -> Vec<String>{
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= width => { line.push(' '); line.push_str(word); },
+            _ => lines.push(word.to_string()),
+        }
+    }
+    return lines;
+}
+
+// The position being set up with the piece palette and the setup controls in the
+// side panel. The piece being dragged follows the mouse at `mouse`.
+pub fn draw_editor(canvas: &mut graphics::Canvas, ctx: &Context, editor: &PositionEditor, piece_images: &PieceImages, error: Option<&str>, mouse: Vec2, theme: &Theme){
+    let setup = &editor.setup;
+    for (index, square) in setup.squares.iter().enumerate() {
+        if let Some((colour, piece_type)) = square.and_then(piece_from_letter) {
+            draw_piece(canvas, piece_images, colour, piece_type, vec2((index % 8 * 90) as f32 + PIECE_OFFSET, (index / 8 * 90) as f32 + PIECE_OFFSET), PIECE_SIZE);
+        }
+    }
+    for (row, letters) in PALETTE.iter().enumerate() {
+        for (column, letter) in letters.iter().enumerate() {
+            if let Some((colour, piece_type)) = piece_from_letter(*letter) {
+                draw_piece(canvas, piece_images, colour, piece_type, vec2(728. + column as f32 * 28., 22. + row as f32 * 34.), PIECE_SIZE * 0.45);
+            }
+        }
+    }
+    draw_toggle(canvas, ctx, Rect::new(725., 100., 170., 32.), if setup.active.is_black() { "Black to move" } else { "White to move" }, true, theme);
+    draw_label(canvas, "Castling", 728., 145., Color::WHITE);
+    for (i, right) in ["K", "Q", "k", "q"].iter().enumerate() {
+        draw_toggle(canvas, ctx, Rect::new(725. + i as f32 * 43., 170., 38., 32.), right, setup.castling[i], theme);
+    }
+    let en_passant = setup.en_passant.map(square_name).unwrap_or(String::from("-"));
+    draw_toggle(canvas, ctx, Rect::new(725., 220., 170., 32.), &format!("e.p. {}", en_passant), setup.en_passant.is_some(), theme);
+    draw_toggle(canvas, ctx, Rect::new(725., 270., 80., 32.), "Clear", false, theme);
+    draw_toggle(canvas, ctx, Rect::new(815., 270., 80., 32.), "Reset", false, theme);
+    for (i, line) in error.map(|error| wrap(error, 14)).unwrap_or_default().iter().enumerate() {
+        draw_label(canvas, line, 728., 320. + i as f32 * 22., Color::RED);
+    }
+    draw_side_button(canvas, ctx, "Use in game", 500.);
+    draw_side_button(canvas, ctx, "Analyse", 560.);
+    if let Some((colour, piece_type)) = editor.held().and_then(|held| piece_from_letter(held.piece)) {
+        draw_piece(canvas, piece_images, colour, piece_type, mouse - Vec2::splat(PIECE_SIZE / 2.), PIECE_SIZE);
+    }
+}
+
+fn draw_label(canvas: &mut graphics::Canvas, text: &str, x: f32, y: f32, color: Color){
+    canvas.draw(
+        &graphics::Text::new(TextFragment{
+            text: text.to_string(),
+            font: Some("LiberationMono".into()),
+            color: Some(color),
+            scale: Some(PxScale::from(20.0)),
+        }), Vec2::new(x, y));
+}
+
+pub fn draw_lobby(canvas: &mut graphics::Canvas, ctx: &Context, lobby: &Lobby, theme: &Theme){
+    let accent = theme_color(theme.accent);
+    let panel = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 400.0, 460.0), Color::from_rgb(40, 40, 40)).unwrap();
+    canvas.draw(&panel, Vec2::new(160., 120.));
+
+    for (mode, label, x) in [(LobbyMode::Host, "Host", 190.), (LobbyMode::Join, "Join", 310.), (LobbyMode::Watch, "Watch", 430.)]{
+        let color = if lobby.mode == mode { accent } else { Color::from_rgb(90, 90, 90) };
+        let button = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 100.0, 44.0), color).unwrap();
+        canvas.draw(&button, Vec2::new(x, 150.));
+        draw_label(canvas, label, x + (100. - label.len() as f32 * 12.) / 2., 162., Color::WHITE);
+    }
+
+    for (row, field) in lobby.fields().iter().enumerate(){
+        let y = 230. + row as f32 * 70.;
+        let label = match field {
+            LobbyField::Bind => "Host on",
+            LobbyField::Port => "Port",
+            LobbyField::Address => "Address",
+            LobbyField::Name => "Name",
+        };
+        draw_label(canvas, label, 190., y, Color::WHITE);
+        let border = if lobby.focused == *field && lobby.is_editable() { accent } else { Color::WHITE };
+        let outline = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::stroke(2.0), Rect::new(0.0, 0.0, 340.0, 40.0), border).unwrap();
+        canvas.draw(&outline, Vec2::new(190., y + 25.));
+        draw_label(canvas, lobby.value(*field), 198., y + 35., Color::WHITE);
+    }
+
+    let (label, color) = match lobby.status {
+        LobbyStatus::Waiting => ("Cancel", Color::RED),
+        _ => match lobby.mode {
+            LobbyMode::Host => ("Host game", accent),
+            LobbyMode::Join => ("Join game", accent),
+            LobbyMode::Watch => ("Watch game", accent),
+        },
+    };
+    let button = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), Rect::new(0.0, 0.0, 340.0, 48.0), color).unwrap();
+    canvas.draw(&button, Vec2::new(190., 445.));
+    draw_label(canvas, label, 310., 459., Color::WHITE);
+
+    match &lobby.status {
+        LobbyStatus::Editing => {},
+        LobbyStatus::Waiting => draw_label(canvas, &lobby.waiting_text(), 190., 510., Color::WHITE),
+        LobbyStatus::Failed(reason) => draw_label(canvas, reason, 190., 510., Color::RED),
+    }
+    // The addresses to give the opponent take the place of the keys while hosting.
+    if lobby.status == LobbyStatus::Waiting && lobby.mode == LobbyMode::Host && !lobby.shared.is_empty() {
+        for (i, address) in lobby.shared.iter().take(2).enumerate() {
+            draw_label(canvas, &format!("Join at {}", address), 190., 534. + i as f32 * 22., Color::from_rgb(118, 200, 86));
+        }
+        return;
+    }
+    let keys = if lobby.fen.is_some() { "Set-up start (F3)" } else { "F2 analyse, F3 set up" };
+    draw_label(canvas, keys, 190., 548., Color::from_rgb(160, 160, 160));
+}
@@ -0,0 +1,133 @@
+use crate::fen::Setup;
+
+// Pieces in the palette, white on the top row.
+pub const PALETTE: [[char; 6]; 2] = [['K', 'Q', 'R', 'B', 'N', 'P'], ['k', 'q', 'r', 'b', 'n', 'p']];
+
+// A piece being dragged, and the square it was lifted from if it came off the board.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Held {
+    pub piece: char,
+    pub from: Option<usize>,
+}
+
+// Board setup mode: pieces are dragged on from the palette, moved around or dragged off again.
+pub struct PositionEditor {
+    pub setup: Setup,
+    held: Option<Held>,
+}
+
+impl PositionEditor {
+    pub fn new(setup: Setup) -> PositionEditor{
+        return PositionEditor { setup, held: None };
+    }
+
+    pub fn held(&self) -> Option<Held>{
+        return self.held;
+    }
+
+    pub fn pick_up_square(&mut self, index: usize){
+        if let Some(piece) = self.setup.squares[index].take() {
+            self.held = Some(Held { piece, from: Some(index) });
+        }
+    }
+
+    pub fn pick_up_spare(&mut self, piece: char){
+        self.held = Some(Held { piece, from: None });
+    }
+
+    // Puts the held piece on `square`, replacing whatever is there. Dropping it off the board removes it.
+    pub fn drop(&mut self, square: Option<usize>){
+        let Some(held) = self.held.take() else {
+            return;
+        };
+        if let Some(square) = square {
+            self.setup.squares[square] = Some(held.piece);
+        }
+        self.setup.drop_impossible_rights();
+    }
+
+    pub fn remove(&mut self, index: usize){
+        self.setup.squares[index] = None;
+        self.setup.drop_impossible_rights();
+    }
+
+    pub fn toggle_active(&mut self){
+        self.setup.active = if self.setup.active.is_black() { chess_lib::Colour::White } else { chess_lib::Colour::Black };
+        self.setup.en_passant = None;
+    }
+
+    // Rights whose king or rook is off its square can't be turned on.
+    pub fn toggle_castling(&mut self, i: usize){
+        self.setup.castling[i] = !self.setup.castling[i] && self.setup.castling_possible(i);
+    }
+
+    // Steps through no en passant square and each square it could be on.
+    pub fn cycle_en_passant(&mut self){
+        let squares = self.setup.en_passant_squares();
+        self.setup.en_passant = match self.setup.en_passant.and_then(|current| squares.iter().position(|square| *square == current)) {
+            Some(i) => squares.get(i + 1).copied(),
+            None => squares.first().copied(),
+        };
+    }
+
+    pub fn clear(&mut self){
+        self.setup = Setup::empty();
+        self.held = None;
+    }
+
+    pub fn reset(&mut self){
+        self.setup = Setup::initial();
+        self.held = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::square;
+
+    #[test]
+    fn pieces_are_dragged_on_moved_and_off() {
+        let mut editor = PositionEditor::new(Setup::empty());
+        editor.pick_up_spare('K');
+        assert_eq!(editor.held(), Some(Held { piece: 'K', from: None }));
+        editor.drop(Some(square("e1")));
+        editor.pick_up_spare('k');
+        editor.drop(Some(square("e8")));
+        assert!(editor.setup.validate().is_ok());
+        editor.pick_up_square(square("e1"));
+        editor.drop(Some(square("d1")));
+        assert_eq!(editor.setup.to_fen(), "4k3/8/8/8/8/8/8/3K4 w - - 0 1");
+        editor.pick_up_square(square("d1"));
+        editor.drop(None);
+        assert!(editor.held().is_none());
+        assert_eq!(editor.setup.to_fen(), "4k3/8/8/8/8/8/8/8 w - - 0 1");
+    }
+
+    #[test]
+    fn rights_follow_the_pieces() {
+        let mut editor = PositionEditor::new(Setup::initial());
+        editor.remove(square("a8"));
+        assert_eq!(editor.setup.castling, [true, true, true, false]);
+        editor.toggle_castling(3);
+        assert_eq!(editor.setup.castling, [true, true, true, false]);
+        editor.toggle_castling(0);
+        assert_eq!(editor.setup.castling, [false, true, true, false]);
+        editor.toggle_castling(0);
+        assert_eq!(editor.setup.castling, [true, true, true, false]);
+    }
+
+    #[test]
+    fn en_passant_cycles_through_candidates() {
+        let mut editor = PositionEditor::new(Setup::parse("4k3/8/8/pP1pP3/8/8/8/4K3 w - -").unwrap());
+        editor.cycle_en_passant();
+        assert_eq!(editor.setup.en_passant, Some(square("a6")));
+        editor.cycle_en_passant();
+        assert_eq!(editor.setup.en_passant, Some(square("d6")));
+        editor.cycle_en_passant();
+        assert_eq!(editor.setup.en_passant, None);
+        editor.cycle_en_passant();
+        editor.toggle_active();
+        assert_eq!(editor.setup.en_passant, None);
+    }
+}
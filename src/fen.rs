@@ -0,0 +1,321 @@
+use chess_lib::{Colour, Game, Move};
+
+use crate::notation::{parse_square, square_name};
+use crate::pieces::piece_letter;
+
+pub const INITIAL: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Castling rights in FEN order, with the king and rook squares each one needs.
+const CASTLING: [(char, usize, usize); 4] = [('K', 60, 63), ('Q', 60, 56), ('k', 4, 7), ('q', 4, 0)];
+
+// A position written out the way FEN has it, so it can be edited square by
+// square before it becomes a `Game`. Pieces are FEN letters, white in capitals.
+#[derive(Clone)]
+pub struct Setup {
+    pub squares: [Option<char>; 64],
+    pub active: Colour,
+    // In `CASTLING` order: white short and long, then black.
+    pub castling: [bool; 4],
+    pub en_passant: Option<usize>,
+    pub halfmove: u32,
+    pub fullmove: u32,
+}
+
+impl Setup {
+    pub fn empty() -> Setup{
+        return Setup { squares: [None; 64], active: Colour::White, castling: [false; 4], en_passant: None, halfmove: 0, fullmove: 1 };
+    }
+
+    pub fn initial() -> Setup{
+        return Setup::parse(INITIAL).unwrap();
+    }
+
+    // The pieces and side to move of `game`. The library doesn't say which
+    // castling rights are left, so any whose king and rook are still home are kept.
+    pub fn from_game(game: &Game) -> Setup{
+        let mut setup = Setup::empty();
+        for (index, piece) in game.get_board().iter().enumerate() {
+            setup.squares[index] = piece.map(|piece| {
+                let letter = piece_letter(piece.piece_type);
+                if piece.colour.is_black() { letter.to_ascii_lowercase() } else { letter }
+            });
+        }
+        setup.active = game.get_active_colour();
+        setup.castling = [true; 4];
+        setup.drop_impossible_rights();
+        return setup;
+    }
+
+    // Plays a legal move, keeping what a `Game` can't tell: the castling rights,
+    // the en passant square and the move counters.
+    pub fn play(&mut self, mv: &Move){
+        let (from, to) = (mv.from.idx, mv.to.idx);
+        let Some(letter) = self.squares[from] else {
+            return;
+        };
+        let capture = self.squares[to].is_some();
+        let pawn = letter.to_ascii_uppercase() == 'P';
+        // A pawn moving diagonally onto an empty square takes the one beside it.
+        if pawn && from % 8 != to % 8 && !capture {
+            self.squares[from / 8 * 8 + to % 8] = None;
+        }
+        if letter.to_ascii_uppercase() == 'K' && from.abs_diff(to) == 2 {
+            let (rook_from, rook_to) = if to > from { (from + 3, from + 1) } else { (from - 4, from - 1) };
+            self.squares[rook_to] = self.squares[rook_from].take();
+        }
+        self.squares[from] = None;
+        self.squares[to] = Some(match mv.promotion_choice {
+            Some(piece_type) if self.active.is_black() => piece_letter(piece_type).to_ascii_lowercase(),
+            Some(piece_type) => piece_letter(piece_type),
+            None => letter,
+        });
+        // A right is gone once its king or rook moves or the rook is taken.
+        for (i, (_, king, rook)) in CASTLING.iter().enumerate() {
+            if [from, to].contains(king) || [from, to].contains(rook) {
+                self.castling[i] = false;
+            }
+        }
+        self.en_passant = if pawn && from.abs_diff(to) == 16 { Some((from + to) / 2) } else { None };
+        self.halfmove = if pawn || capture { 0 } else { self.halfmove + 1 };
+        if self.active.is_black() {
+            self.fullmove += 1;
+        }
+        self.active = if self.active.is_black() { Colour::White } else { Colour::Black };
+    }
+
+    // Reads all six fields, or just the first four with the move counters left at their start.
+    pub fn parse(fen: &str) -> Result<Setup, String>{
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() != 4 && fields.len() != 6 {
+            return Err(String::from("A FEN needs 4 or 6 fields"));
+        }
+        let mut setup = Setup::empty();
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(String::from("The board needs 8 ranks"));
+        }
+        for (row, rank) in ranks.iter().enumerate() {
+            let mut file = 0;
+            for letter in rank.chars() {
+                match letter.to_digit(10) {
+                    Some(skip @ 1..=8) => file += skip as usize,
+                    Some(_) => return Err(format!("Bad square count in rank {}", 8 - row)),
+                    None if "KQRBNPkqrbnp".contains(letter) => {
+                        if file < 8 {
+                            setup.squares[row * 8 + file] = Some(letter);
+                        }
+                        file += 1;
+                    },
+                    None => return Err(format!("Unknown piece '{}'", letter)),
+                }
+            }
+            if file != 8 {
+                return Err(format!("Rank {} is not 8 squares", 8 - row));
+            }
+        }
+        setup.active = match fields[1] {
+            "w" => Colour::White,
+            "b" => Colour::Black,
+            _ => return Err(String::from("Side to move must be w or b")),
+        };
+        if fields[2] != "-" {
+            for letter in fields[2].chars() {
+                match CASTLING.iter().position(|(right, _, _)| *right == letter) {
+                    Some(i) => setup.castling[i] = true,
+                    None => return Err(format!("Unknown castling right '{}'", letter)),
+                }
+            }
+        }
+        if fields[3] != "-" {
+            setup.en_passant = Some(parse_square(fields[3]).ok_or_else(|| format!("Bad en passant square '{}'", fields[3]))?);
+        }
+        if fields.len() == 6 {
+            setup.halfmove = fields[4].parse().map_err(|_| String::from("Bad halfmove clock"))?;
+            setup.fullmove = fields[5].parse().map_err(|_| String::from("Bad move number"))?;
+        }
+        return Ok(setup);
+    }
+
+    pub fn to_fen(&self) -> String{
+        let mut board = String::new();
+        for row in 0..8 {
+            let mut empty = 0;
+            for file in 0..8 {
+                match self.squares[row * 8 + file] {
+                    Some(letter) => {
+                        if empty > 0 {
+                            board.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        board.push(letter);
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                board.push_str(&empty.to_string());
+            }
+            if row < 7 {
+                board.push('/');
+            }
+        }
+        let castling: String = CASTLING.iter().zip(self.castling).filter(|(_, on)| *on).map(|((right, _, _), _)| *right).collect();
+        return format!("{} {} {} {} {} {}",
+            board,
+            if self.active.is_black() { "b" } else { "w" },
+            if castling.is_empty() { "-" } else { &castling },
+            self.en_passant.map(square_name).unwrap_or(String::from("-")),
+            self.halfmove,
+            self.fullmove);
+    }
+
+    // Whether castling right `i` still has its king and rook on their starting squares.
+    pub fn castling_possible(&self, i: usize) -> bool{
+        let (right, king, rook) = CASTLING[i];
+        let (king_letter, rook_letter) = if right.is_uppercase() { ('K', 'R') } else { ('k', 'r') };
+        return self.squares[king] == Some(king_letter) && self.squares[rook] == Some(rook_letter);
+    }
+
+    // Squares a pawn could be taken en passant on: just behind a pawn of the side
+    // not to move that could have come up two squares on the last move.
+    pub fn en_passant_squares(&self) -> Vec<usize>{
+        let (row, pawn) = if self.active.is_black() { (5, 'P') } else { (2, 'p') };
+        let forward: i32 = if self.active.is_black() { -8 } else { 8 };
+        return (row * 8..row * 8 + 8).filter(|square| {
+            let pawn_square = (*square as i32 + forward) as usize;
+            let start = (*square as i32 - forward) as usize;
+            self.squares[*square].is_none() && self.squares[start].is_none() && self.squares[pawn_square] == Some(pawn)
+        }).collect();
+    }
+
+    // Clears castling rights and the en passant square once the pieces they need have moved.
+    pub fn drop_impossible_rights(&mut self){
+        self.castling = std::array::from_fn(|i| self.castling[i] && self.castling_possible(i));
+        if self.en_passant.is_some_and(|square| !self.en_passant_squares().contains(&square)) {
+            self.en_passant = None;
+        }
+    }
+
+    // Checks the position could come up in a game, with a message for the first problem found.
+    pub fn validate(&self) -> Result<(), String>{
+        for (king, side) in [('K', "white"), ('k', "black")] {
+            let count = self.squares.iter().filter(|square| **square == Some(king)).count();
+            if count != 1 {
+                return Err(format!("Need one {} king", side));
+            }
+        }
+        let back_ranks = self.squares[..8].iter().chain(&self.squares[56..]);
+        if back_ranks.filter_map(|square| *square).any(|letter| letter.to_ascii_uppercase() == 'P') {
+            return Err(String::from("Pawn on back rank"));
+        }
+        if (0..CASTLING.len()).any(|i| self.castling[i] && !self.castling_possible(i)) {
+            return Err(String::from("Castling not possible"));
+        }
+        if self.en_passant.is_some_and(|square| !self.en_passant_squares().contains(&square)) {
+            return Err(String::from("Bad en passant"));
+        }
+        return Ok(());
+    }
+
+    // The position as a game to play or analyse, if it is a legal one.
+    pub fn to_game(&self) -> Result<Game, String>{
+        self.validate()?;
+        return Game::from_fen(&self.to_fen()).map_err(|_| String::from("Position not accepted"));
+    }
+}
+
+// Whether two FENs have the same pieces and side to move. The rest can differ
+// between two records of one game, `Setup::from_game` has to guess the castling rights.
+pub fn same_position(a: &str, b: &str) -> bool{
+    return a.split_whitespace().take(2).eq(b.split_whitespace().take(2));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::notation::{parse_move, ParsedMove};
+    use crate::test_support::square;
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(Setup::initial().to_fen(), INITIAL);
+        let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 23";
+        assert_eq!(Setup::parse(fen).unwrap().to_fen(), fen);
+        let short = Setup::parse("8/8/8/8/8/8/8/K6k b - -").unwrap();
+        assert_eq!(short.to_fen(), "8/8/8/8/8/8/8/K6k b - - 0 1");
+    }
+
+    #[test]
+    fn rejects_malformed_fen() {
+        assert!(Setup::parse("").is_err());
+        assert!(Setup::parse("8/8/8/8/8/8/8 w - -").is_err());
+        assert!(Setup::parse("9/8/8/8/8/8/8/8 w - -").is_err());
+        assert!(Setup::parse("7/8/8/8/8/8/8/8 w - -").is_err());
+        assert!(Setup::parse("x7/8/8/8/8/8/8/8 w - -").is_err());
+        assert!(Setup::parse("8/8/8/8/8/8/8/8 white - -").is_err());
+        assert!(Setup::parse("8/8/8/8/8/8/8/8 w X -").is_err());
+        assert!(Setup::parse("8/8/8/8/8/8/8/8 w - z9").is_err());
+        assert!(Setup::parse("8/8/8/8/8/8/8/8 w - - x 1").is_err());
+    }
+
+    #[test]
+    fn validates_kings_pawns_and_rights() {
+        assert!(Setup::initial().validate().is_ok());
+        assert_eq!(Setup::empty().validate(), Err(String::from("Need one white king")));
+        let two_kings = Setup::parse("k7/8/8/8/8/8/8/KK6 w - -").unwrap();
+        assert_eq!(two_kings.validate(), Err(String::from("Need one white king")));
+        let pawn = Setup::parse("k6P/8/8/8/8/8/8/K7 w - -").unwrap();
+        assert_eq!(pawn.validate(), Err(String::from("Pawn on back rank")));
+        let castling = Setup::parse("k7/8/8/8/8/8/8/4K3 w K -").unwrap();
+        assert_eq!(castling.validate(), Err(String::from("Castling not possible")));
+        let en_passant = Setup::parse("k7/8/8/8/8/8/8/4K3 w - e6").unwrap();
+        assert_eq!(en_passant.validate(), Err(String::from("Bad en passant")));
+    }
+
+    #[test]
+    fn finds_en_passant_squares() {
+        let setup = Setup::parse("k7/8/8/3pP3/8/8/8/K7 w - -").unwrap();
+        assert_eq!(setup.en_passant_squares(), vec![square("d6")]);
+        let setup = Setup::parse("k7/8/8/8/4P3/8/8/K7 b - -").unwrap();
+        assert_eq!(setup.en_passant_squares(), vec![square("e3")]);
+    }
+
+    #[test]
+    fn moving_pieces_drops_their_rights() {
+        let mut setup = Setup::initial();
+        setup.squares[square("h1")] = None;
+        setup.drop_impossible_rights();
+        assert_eq!(setup.to_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w Qkq - 0 1");
+    }
+
+    #[test]
+    fn playing_moves_keeps_rights_and_counters() {
+        let mut setup = Setup::initial();
+        let mut game = Game::new();
+        for uci in ["e2e4", "g8f6", "e4e5", "d7d5", "e5d6", "h8g8", "g1f3", "g8h8", "f1e2", "b8c6", "e1g1"] {
+            let ParsedMove::Legal(mv) = parse_move(&game, uci) else {
+                panic!("{} should be legal", uci);
+            };
+            game.make_move(mv).unwrap();
+            setup.play(&mv);
+            assert!(same_position(&setup.to_fen(), &Setup::from_game(&game).to_fen()), "boards differ after {}", uci);
+            if uci == "d7d5" {
+                assert_eq!(setup.to_fen(), "rnbqkb1r/ppp1pppp/5n2/3pP3/8/8/PPPP1PPP/RNBQKBNR w KQkq d6 0 3");
+            }
+        }
+        // The black rook went back home, but black can't castle short any more.
+        assert_eq!(setup.to_fen(), "r1bqkb1r/ppp1pppp/2nP1n2/8/8/5N2/PPPPBPPP/RNBQ1RK1 b q - 6 6");
+    }
+
+    #[test]
+    fn compares_pieces_and_side_to_move() {
+        assert!(same_position(INITIAL, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 3 9"));
+        assert!(!same_position(INITIAL, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 0 1"));
+        assert!(!same_position(INITIAL, "4k3/8/8/8/8/8/8/4K3 w - - 0 1"));
+    }
+
+    #[test]
+    fn reads_games() {
+        assert_eq!(Setup::from_game(&Game::new()).to_fen(), INITIAL);
+    }
+}
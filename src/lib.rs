@@ -0,0 +1,19 @@
+pub mod network;
+pub mod controller;
+pub mod lobby;
+pub mod animation;
+pub mod material;
+pub mod theme;
+pub mod pieces;
+pub mod svg;
+pub mod viewport;
+pub mod notation;
+pub mod annotations;
+pub mod analysis;
+pub mod fen;
+pub mod editor;
+pub mod save;
+pub mod spectator;
+pub mod chat;
+#[cfg(test)]
+mod test_support;
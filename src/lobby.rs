@@ -0,0 +1,226 @@
+// What the connection screen shows before a game starts.
+
+use crate::network::{split_host_port, DEFAULT_BIND};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LobbyMode {
+    Host,
+    Join,
+    // Following a game someone else hosts, without playing.
+    Watch,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LobbyField {
+    Bind,
+    Port,
+    Address,
+    Name,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LobbyStatus {
+    Editing,
+    Waiting,
+    Failed(String),
+}
+
+pub struct Lobby {
+    pub mode: LobbyMode,
+    // The local address to host on.
+    pub bind: String,
+    pub port: String,
+    pub address: String,
+    pub name: String,
+    pub focused: LobbyField,
+    pub status: LobbyStatus,
+    // FEN of a set-up position to host the game from, none for the usual start.
+    pub fen: Option<String>,
+    // Where the opponent can join a game we host, filled in by whoever starts hosting.
+    pub shared: Vec<String>,
+}
+
+impl Lobby {
+    pub fn new() -> Lobby{
+        return Lobby {
+            mode: LobbyMode::Host,
+            bind: String::from(DEFAULT_BIND),
+            port: String::from("8080"),
+            address: String::from("127.0.0.1:8080"),
+            name: String::new(),
+            focused: LobbyField::Bind,
+            status: LobbyStatus::Editing,
+            fen: None,
+            shared: Vec::new(),
+        };
+    }
+
+    // The fields shown for the current mode, top to bottom.
+    pub fn fields(&self) -> &'static [LobbyField]{
+        return match self.mode {
+            LobbyMode::Host => &[LobbyField::Bind, LobbyField::Port, LobbyField::Name],
+            LobbyMode::Join | LobbyMode::Watch => &[LobbyField::Address, LobbyField::Name],
+        };
+    }
+
+    pub fn value(&self, field: LobbyField) -> &str{
+        return match field {
+            LobbyField::Bind => &self.bind,
+            LobbyField::Port => &self.port,
+            LobbyField::Address => &self.address,
+            LobbyField::Name => &self.name,
+        };
+    }
+
+    fn value_mut(&mut self, field: LobbyField) -> &mut String{
+        return match field {
+            LobbyField::Bind => &mut self.bind,
+            LobbyField::Port => &mut self.port,
+            LobbyField::Address => &mut self.address,
+            LobbyField::Name => &mut self.name,
+        };
+    }
+
+    pub fn is_editable(&self) -> bool{
+        return self.status != LobbyStatus::Waiting;
+    }
+
+    pub fn set_mode(&mut self, mode: LobbyMode){
+        if !self.is_editable() {
+            return;
+        }
+        self.mode = mode;
+        self.focused = self.fields()[0];
+    }
+
+    pub fn focus(&mut self, field: LobbyField){
+        if self.is_editable() && self.fields().contains(&field) {
+            self.focused = field;
+        }
+    }
+
+    pub fn focus_next(&mut self){
+        let fields = self.fields();
+        let current = fields.iter().position(|&field| field == self.focused).unwrap_or(0);
+        self.focus(fields[(current + 1) % fields.len()]);
+    }
+
+    pub fn type_char(&mut self, character: char){
+        if !self.is_editable() || character.is_control() {
+            return;
+        }
+        if self.focused == LobbyField::Port && !character.is_ascii_digit() {
+            return;
+        }
+        let focused = self.focused;
+        self.value_mut(focused).push(character);
+    }
+
+    pub fn backspace(&mut self){
+        if !self.is_editable() {
+            return;
+        }
+        let focused = self.focused;
+        self.value_mut(focused).pop();
+    }
+
+    // Checks the fields and marks the lobby as waiting, the caller starts the actual connection.
+    pub fn start(&mut self) -> Result<(), String>{
+        if !self.is_editable() {
+            return Err(String::from("Already connecting"));
+        }
+        let result = match self.mode {
+            LobbyMode::Host if self.bind.trim().is_empty() => Err(String::from("Enter the address to host on")),
+            LobbyMode::Host if self.port.parse::<u16>().is_err() => Err(String::from("Enter a port between 0 and 65535")),
+            LobbyMode::Join | LobbyMode::Watch if self.address.trim().is_empty() => Err(String::from("Enter the address to join")),
+            LobbyMode::Join | LobbyMode::Watch => split_host_port(&self.address).map(|_| ()),
+            _ => Ok(()),
+        };
+        self.status = match &result {
+            Ok(()) => LobbyStatus::Waiting,
+            Err(err) => LobbyStatus::Failed(err.clone()),
+        };
+        return result;
+    }
+
+    pub fn cancel(&mut self){
+        self.status = LobbyStatus::Editing;
+    }
+
+    pub fn failed(&mut self, reason: String){
+        self.status = LobbyStatus::Failed(reason);
+    }
+
+    pub fn waiting_text(&self) -> String{
+        return match self.mode {
+            LobbyMode::Host => format!("Waiting for opponent on port {}", self.port),
+            LobbyMode::Join => format!("Connecting to {}", self.address),
+            LobbyMode::Watch => format!("Waiting to watch {}", self.address),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typing_goes_to_the_focused_field() {
+        let mut lobby = Lobby::new();
+        lobby.focus(LobbyField::Port);
+        lobby.port.clear();
+        lobby.type_char('9');
+        lobby.type_char('x');
+        lobby.type_char('1');
+        assert_eq!(lobby.port, "91");
+        lobby.focus_next();
+        lobby.type_char('A');
+        assert_eq!(lobby.name, "A");
+        lobby.backspace();
+        assert_eq!(lobby.name, "");
+    }
+
+    #[test]
+    fn switching_mode_shows_the_address_field() {
+        let mut lobby = Lobby::new();
+        lobby.set_mode(LobbyMode::Join);
+        assert_eq!(lobby.fields(), [LobbyField::Address, LobbyField::Name]);
+        assert_eq!(lobby.focused, LobbyField::Address);
+        lobby.focus(LobbyField::Port);
+        assert_eq!(lobby.focused, LobbyField::Address);
+        lobby.set_mode(LobbyMode::Host);
+        assert_eq!(lobby.fields(), [LobbyField::Bind, LobbyField::Port, LobbyField::Name]);
+    }
+
+    #[test]
+    fn fields_are_locked_while_waiting() {
+        let mut lobby = Lobby::new();
+        assert!(lobby.start().is_ok());
+        assert_eq!(lobby.waiting_text(), "Waiting for opponent on port 8080");
+        lobby.type_char('1');
+        lobby.set_mode(LobbyMode::Join);
+        assert_eq!(lobby.port, "8080");
+        assert_eq!(lobby.mode, LobbyMode::Host);
+        lobby.cancel();
+        assert!(lobby.is_editable());
+    }
+
+    #[test]
+    fn invalid_port_is_reported() {
+        let mut lobby = Lobby::new();
+        lobby.port = String::from("99999");
+        assert!(lobby.start().is_err());
+        assert!(matches!(lobby.status, LobbyStatus::Failed(_)));
+        assert!(lobby.is_editable());
+    }
+
+    #[test]
+    fn join_address_needs_a_port() {
+        let mut lobby = Lobby::new();
+        lobby.set_mode(LobbyMode::Join);
+        lobby.address = String::from("::1");
+        assert_eq!(lobby.start(), Err(String::from("Put IPv6 addresses in brackets, like [::1]:8080")));
+        lobby.address = String::from("[::1]:8080");
+        assert!(lobby.start().is_ok());
+    }
+}
@@ -1,115 +1,1017 @@
 
 
-use std::net::TcpStream;
-use std::time::Instant;
-use std::{env, path};
+use std::io::Read;
+use std::time::{Duration, Instant};
+use std::{env, fs, path};
 
 use chess_lib::{Colour, GameOverReason, GameState, Move, Position};
-use draw::{draw_board_indexing, draw_board_pieces, draw_board_rectangles, draw_game_over_window, draw_highlighted_squares, draw_promotion_selection_window, draw_request_draw_button};
+use draw::{draw_analysis_panel, draw_annotations, draw_editor, draw_message_window, draw_board_indexing, draw_board_pieces, draw_board_rectangles, draw_captured_pieces, draw_chat, draw_chat_hint, draw_check_glow, draw_cursor, draw_offer_window, draw_game_over_window, draw_highlighted_squares, draw_last_move, draw_link_status, draw_lobby, draw_move_entry, draw_name_plate, draw_premoves, draw_promotion_selection_window, draw_request_draw_button, draw_selected_square, draw_side_button, draw_spectator_panel, draw_status_text, game_over_text, PIECE_SIZE};
 use ggez::conf::FullscreenType;
 use ggez::event::MouseButton;
+use ggez::input::keyboard::{KeyCode, KeyInput, KeyMods};
 use ggez::mint::Point2;
 use ggez::{context, event, GameError};
 use ggez::graphics::{self, Color, DrawParam, Drawable, Mesh, PxScale, Rect, TextFragment};
 use ggez::{Context, GameResult};
 use ggez::glam::*;
-use network::{await_move, chess_lib_state_to_network_state, do_move, send_ack, start_client, start_server};
+use caspervk_chess_gui::analysis::AnalysisBoard;
+use caspervk_chess_gui::animation::{self, Animation};
+use caspervk_chess_gui::annotations::{Annotations, MarkColour};
+use caspervk_chess_gui::chat::ChatLog;
+use caspervk_chess_gui::editor::{PositionEditor, PALETTE};
+use caspervk_chess_gui::fen::{same_position, Setup, INITIAL};
+use caspervk_chess_gui::controller::{checked_king, Command, ResyncOutcome, EndReason, GameController, Input, UiState};
+use caspervk_chess_gui::lobby::{Lobby, LobbyMode};
+use caspervk_chess_gui::material::material;
+use caspervk_chess_gui::notation::{is_move_char, parse_move, uci, ParsedMove};
+use caspervk_chess_gui::pieces::{file_name, sprite_cell, PieceAtlas, COLOURS, PIECE_TYPES};
+use caspervk_chess_gui::save::{self, SavedGame};
+use caspervk_chess_gui::spectator::SpectatorView;
+use caspervk_chess_gui::svg;
+use caspervk_chess_gui::viewport::{self, Viewport};
+use caspervk_chess_gui::theme::{parse_themes, Theme};
+use caspervk_chess_gui::network::{self, to_network_move, Broadcast, Connection, LinkStatus, NetworkEvent, CHAT, RESYNC, TAKEBACK};
 
 mod draw;
-mod network;
-struct PieceImages{
-    black_rook: graphics::Image,
-    black_knight: graphics::Image,
-    black_bishop: graphics::Image,
-    black_king: graphics::Image,
-    black_queen: graphics::Image,
-    black_pawn: graphics::Image,
-
-    white_rook: graphics::Image,
-    white_knight: graphics::Image,
-    white_bishop: graphics::Image,
-    white_king: graphics::Image,
-    white_queen: graphics::Image,
-    white_pawn: graphics::Image,
+// One piece's image, either a whole file or a cell of a sprite sheet.
+#[derive(Clone)]
+struct PieceSprite{
+    image: graphics::Image,
+    src: Rect,
+    // Width in pixels of the part of `image` the piece covers.
+    width: f32,
 }
+type PieceImages = PieceAtlas<PieceSprite>;
+
+// Loads an image resource, rasterizing SVGs to `width` by `height` pixels.
+fn load_image(ctx: &Context, path: &str, width: u32, height: u32) -> GameResult<graphics::Image>{
+    if !path.ends_with(".svg") {
+        return graphics::Image::from_path(ctx, path);
+    }
+    let mut data = Vec::new();
+    ctx.fs.open(path)?.read_to_end(&mut data)?;
+    let pixels = svg::rasterize(&data, width, height).map_err(GameError::ResourceLoadError)?;
+    return Ok(graphics::Image::from_pixels(ctx, &pixels, graphics::ImageFormat::Rgba8UnormSrgb, width, height));
+}
+
+// SVG pieces are rasterized at `pixels` wide so they stay sharp, bitmaps are scaled when drawn.
+fn load_piece_set(ctx: &Context, theme: &Theme, pixels: u32) -> PieceImages{
+    if let Some(sheet) = &theme.piece_sheet {
+        match load_image(ctx, sheet, pixels * PIECE_TYPES.len() as u32, pixels * COLOURS.len() as u32) {
+            Ok(image) => return PieceAtlas::load_with(|colour, piece_type| {
+                let [x, y, w, h] = sprite_cell(colour, piece_type);
+                Some(PieceSprite{ image: image.clone(), src: Rect::new(x, y, w, h), width: image.width() as f32 * w })
+            }),
+            Err(err) => println!("Could not load sprite sheet {}, using {} instead: {}", sheet, theme.pieces, err),
+        }
+    }
+    return PieceAtlas::load_with(|colour, piece_type| {
+        let image = ["svg", "png"].iter().find_map(|extension| {
+            load_image(ctx, &theme.piece_path(&file_name(colour, piece_type, extension)), pixels, pixels).ok()
+        })?;
+        Some(PieceSprite{ width: image.width() as f32, src: Rect::one(), image })
+    });
+}
+
+// Pieces missing from a theme's set come from the default set, and are drawn as letters if that lacks them too.
+fn load_piece_images(ctx: &Context, theme: &Theme, pixels: u32) -> PieceImages{
+    let mut piece_images = load_piece_set(ctx, theme, pixels);
+    if !piece_images.is_complete() {
+        println!("Theme {} is missing some pieces, using the default ones instead", theme.name);
+        piece_images.fill_from(load_piece_set(ctx, &Theme::default(), pixels));
+    }
+    return piece_images;
+}
+
+// Themes come from resources/themes.toml, a broken or missing file falls back to the built-in look.
+fn load_themes(ctx: &Context) -> Vec<Theme>{
+    let mut text = String::new();
+    let read = ctx.fs.open("/themes.toml").and_then(|mut file| Ok(file.read_to_string(&mut text)?));
+    let parsed = read.map_err(|err| err.to_string()).and_then(|_| parse_themes(&text));
+    return match parsed {
+        Ok(themes) => themes,
+        Err(err) => {
+            println!("Could not load themes.toml, using the default theme: {}", err);
+            vec![Theme::default()]
+        }
+    };
+}
+
 struct MainState {
-    board: chess_lib::Game,
-    stream: TcpStream,
+    controller: GameController,
+    connection: Option<Connection>,
+    lobby: Lobby,
     piece_images: PieceImages,
+    // Width in window pixels SVG pieces were last rasterized at.
+    piece_pixels: u32,
+    viewport: Viewport,
+    themes: Vec<Theme>,
+    theme_index: usize,
 
-    player_side: chess_lib::Colour,
     opponent_name: Option<String>,
+    // Protocol extensions the opponent announced, see `network::EXTENSIONS`.
+    peer_extensions: Vec<String>,
+    // Sent to the opponent, played once they ack it.
+    pending_move: Option<chess_lib::Move>,
+    connection_lost: Option<String>,
+    // The opponent's heartbeats stopped coming for a while.
+    lagging: bool,
+    chat: ChatLog,
+    connection_timeout: Duration,
+
+    animation: Option<Animation>,
+    animation_duration: Duration,
+    // What the board looked like after the last move we started animating.
+    shown_board: [Option<chess_lib::Piece>; 64],
+    shown_ply: usize,
+
+    annotations: Annotations,
+    // Where a right-button drag started, if it was on the board.
+    annotation_start: Option<usize>,
+
+    // Keyboard play: a move typed as text, and a square picked with the arrow keys.
+    move_text: String,
+    cursor: Option<usize>,
+
+    // Open over the lobby or a finished game, with a move waiting for its promotion piece.
+    analysis: Option<AnalysisBoard>,
+    analysis_promotion: Option<Move>,
+    // Setting up a position from the lobby, with why it can't be used if that was tried.
+    editor: Option<PositionEditor>,
+    editor_error: Option<String>,
+
+    // Kept open for the whole run, on some systems copied text is gone once it closes.
+    clipboard: Option<arboard::Clipboard>,
+    // Shown over everything until the next click or key press.
+    clipboard_error: Option<String>,
+
+    // Where the game is saved on quit. While resuming, the opponent has to be at the same
+    // position and we have to get the side we played before.
+    save_path: String,
+    resuming: Option<chess_lib::Colour>,
+    // Set from when the connection drops mid-game until both sides agree on the position again.
+    reconnect: Option<Reconnect>,
+
+    // Watching someone else's game, from the first broadcast until Escape.
+    spectating: Option<SpectatorView>,
+    // The last update sent to spectators of a game we host.
+    broadcast_sent: Option<Broadcast>,
 
-    mouse_down: bool,
     mouse_down_x: f32,
     mouse_down_y: f32,
     last_click_time: Instant,
-
-    highlighted_movements: Option<Vec<u32>>,
-    selected_piece_index: Option<usize>,
-    awaiting_promotion_choice: bool,
-    move_to_make_after_promotion: Option<chess_lib::Move>,
 }
 
 impl MainState {
     fn new(ctx: &mut Context) -> GameResult<MainState> {
-        let args: Vec<_> = env::args().collect();
-        let mut stream;
-        let mut opponent_name;
-        let mut player_side = chess_lib::Colour::White; // Who doesnt want to start white:)
-        println!(" {} {} ", args[2], args[3]);
-        let mut board = chess_lib::Game::new();
-        if(args.len() < 3){
-            return GameResult::Err((GameError::CustomError("Invalid command line arguments.".to_string())));
-        }
-        if(args[1] == "s"){
-            (stream, opponent_name) = start_server(&args[2], &args[3]);
-        }
-        else if(args[1] == "c"){
-            (stream, opponent_name, player_side) = start_client(&args[2], &args[3]);
-            let _Move = await_move(&mut stream);
-            board.make_move(Move::new(&board, Position::new(_Move.from.1 as usize, _Move.from.0 as usize).unwrap(), Position::new(_Move.to.1 as usize, _Move.to.0 as usize).unwrap()).unwrap());
-            send_ack(&mut stream, true, None);
-        }
-        else{
-            return GameResult::Err((GameError::CustomError("Invalid command line arguments. Please select if server or client with s or c as first argument".to_string())));
-        }
-
         ctx.gfx.add_font(
             "LiberationMono",
             graphics::FontData::from_path(ctx, "/LiberationMono-Regular.ttf")?,
         );
+        // Coordinate fonts are registered under their path, a font that fails to load falls back to LiberationMono.
+        let mut themes = load_themes(ctx);
+        for theme in themes.iter_mut() {
+            match graphics::FontData::from_path(ctx, &theme.coordinate_font) {
+                Ok(font) => ctx.gfx.add_font(&theme.coordinate_font, font),
+                Err(_) => theme.coordinate_font = String::from("LiberationMono"),
+            }
+        }
+        let (width, height) = ctx.gfx.drawable_size();
+        let viewport = Viewport::fit(width, height);
         let mut s = MainState { 
-            board: board, 
-            piece_images: PieceImages{
-            black_rook: graphics::Image::from_path(ctx, "/r_black.png")?,
-            black_knight: graphics::Image::from_path(ctx, "/n_black.png")?,
-            black_bishop: graphics::Image::from_path(ctx, "/b_black.png")?,
-            black_king: graphics::Image::from_path(ctx, "/k_black.png")?,
-            black_queen: graphics::Image::from_path(ctx, "/q_black.png")?,
-            black_pawn: graphics::Image::from_path(ctx, "/p_black.png")?,
-
-            white_rook: graphics::Image::from_path(ctx, "/r_white.png")?,
-            white_knight: graphics::Image::from_path(ctx, "/n_white.png")?,
-            white_bishop: graphics::Image::from_path(ctx, "/b_white.png")?,
-            white_king: graphics::Image::from_path(ctx, "/k_white.png")?,
-            white_queen: graphics::Image::from_path(ctx, "/q_white.png")?,
-            white_pawn: graphics::Image::from_path(ctx, "/p_white.png")?,
-            },
-            mouse_down: false,
+            controller: GameController::new(chess_lib::Game::new(), Setup::initial()),
+            connection: None,
+            lobby: Lobby::new(),
+            piece_images: load_piece_images(ctx, &themes[0], viewport.pixels(PIECE_SIZE)),
+            piece_pixels: viewport.pixels(PIECE_SIZE),
+            viewport,
+            themes: themes,
+            theme_index: 0,
+            opponent_name: None,
+            peer_extensions: Vec::new(),
+            pending_move: None,
+            connection_lost: None,
+            lagging: false,
+            chat: ChatLog::new(),
+            connection_timeout: connection_timeout(),
+            animation: None,
+            animation_duration: animation_duration(),
+            shown_board: chess_lib::Game::new().get_board(),
+            shown_ply: 0,
+            annotations: Annotations::new(),
+            annotation_start: None,
+            move_text: String::new(),
+            cursor: None,
+            analysis: None,
+            analysis_promotion: None,
+            editor: None,
+            editor_error: None,
+            clipboard: arboard::Clipboard::new().ok(),
+            clipboard_error: None,
+            save_path: String::from(save::DEFAULT_PATH),
+            resuming: None,
+            reconnect: None,
+            spectating: None,
+            broadcast_sent: None,
             mouse_down_x: 0.,
             mouse_down_y: 0.,
-            highlighted_movements: None,
-            selected_piece_index: None,
             last_click_time: Instant::now(),
-            awaiting_promotion_choice: false,        
-            move_to_make_after_promotion: None,
-            opponent_name: opponent_name,
-            stream: stream,
-            player_side: player_side
         };
+
+        // `s [bind:]<port> <name>`, `c <address> <name>` or `w <address> <name>` fills in the lobby and connects right away,
+        // `resume [file]` picks up a game saved when the window was closed.
+        let args: Vec<_> = env::args().collect();
+        if(args.len() >= 2 && args[1] == "resume"){
+            let path = args.get(2).cloned().unwrap_or(String::from(save::DEFAULT_PATH));
+            if let Err(err) = s.resume(&path) {
+                return GameResult::Err(GameError::CustomError(format!("Could not resume {}: {}", path, err)));
+            }
+        }
+        else if(args.len() >= 4){
+            match args[1].as_str() {
+                "s" => {
+                    s.lobby.set_mode(LobbyMode::Host);
+                    match network::split_host_port(&args[2]) {
+                        Ok((bind, port)) => {
+                            s.lobby.bind = bind;
+                            s.lobby.port = port.to_string();
+                        },
+                        Err(_) => s.lobby.port = args[2].clone(),
+                    }
+                },
+                "c" => {
+                    s.lobby.set_mode(LobbyMode::Join);
+                    s.lobby.address = args[2].clone();
+                },
+                "w" => {
+                    s.lobby.set_mode(LobbyMode::Watch);
+                    s.lobby.address = args[2].clone();
+                },
+                _ => return GameResult::Err(GameError::CustomError("Invalid command line arguments. Please select if server, client or spectator with s, c or w as first argument".to_string())),
+            }
+            s.lobby.name = args[3].clone();
+            s.start_connecting();
+        }
         return Ok(s);
     }
+
+    // Starts sliding the pieces whenever the controller has played a new move.
+    fn update_animation(&mut self, delta: Duration){
+        if let Some(animation) = self.animation.as_mut() {
+            animation.advance(delta);
+            if animation.is_finished() {
+                self.animation = None;
+            }
+        }
+        let ply = self.controller.ply();
+        if ply == self.shown_ply {
+            return;
+        }
+        // A takeback jumps straight back instead of sliding.
+        self.animation = match self.controller.last_move() {
+            Some((from, to)) if ply == self.shown_ply + 1 => Animation::new(&self.shown_board, from, to, self.animation_duration),
+            _ => None,
+        };
+        self.shown_board = self.controller.board().get_board();
+        self.shown_ply = ply;
+    }
+
+    fn theme(&self) -> &Theme{
+        return &self.themes[self.theme_index];
+    }
+
+    fn next_theme(&mut self, ctx: &Context){
+        self.theme_index = (self.theme_index + 1) % self.themes.len();
+        self.piece_images = load_piece_images(ctx, self.theme(), self.piece_pixels);
+    }
+
+    fn start_connecting(&mut self){
+        if self.lobby.start().is_err() {
+            return;
+        }
+        let name = self.lobby.name.clone();
+        self.broadcast_sent = None;
+        if let (LobbyMode::Host, Ok(port)) = (self.lobby.mode, self.lobby.port.parse()) {
+            self.lobby.shared = network::shareable_addresses(&self.lobby.bind, port);
+        }
+        self.connection = Some(match self.lobby.mode {
+            LobbyMode::Host => Connection::host(self.lobby.bind.clone(), self.lobby.port.clone(), name, self.lobby.fen.clone(), self.connection_timeout),
+            LobbyMode::Join => Connection::join(self.lobby.address.clone(), name, self.connection_timeout),
+            LobbyMode::Watch => Connection::watch(self.lobby.address.clone(), name),
+        });
+    }
+
+    fn start_from(&mut self, game: chess_lib::Game, setup: Setup){
+        self.shown_board = game.get_board();
+        self.shown_ply = 0;
+        self.controller = GameController::new(game, setup);
+    }
+
+    // Rebuilds a saved game and connects the way it was played before.
+    fn resume(&mut self, path: &str) -> Result<(), String>{
+        let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let saved = SavedGame::from_toml(&text)?;
+        let (start, moves) = saved.replay()?;
+        self.controller = GameController::new(start, Setup::parse(&saved.start)?);
+        self.controller.replay(&moves);
+        self.shown_board = self.controller.board().get_board();
+        self.shown_ply = self.controller.ply();
+        self.save_path = String::from(path);
+        self.opponent_name = saved.opponent_name;
+        self.chat = ChatLog::with_lines(saved.chat);
+        self.lobby.name = saved.name;
+        if saved.host {
+            self.lobby.set_mode(LobbyMode::Host);
+            self.lobby.port = saved.address;
+            if let Some(bind) = saved.bind {
+                self.lobby.bind = bind;
+            }
+            self.lobby.fen = Some(self.controller.fen());
+        }
+        else {
+            self.lobby.set_mode(LobbyMode::Join);
+            self.lobby.address = saved.address;
+        }
+        self.resuming = Some(if saved.playing_white { chess_lib::Colour::White } else { chess_lib::Colour::Black });
+        self.start_connecting();
+        return Ok(());
+    }
+
+    // The game as it stands, if there is one in progress worth coming back to.
+    fn saved_game(&self) -> Option<SavedGame>{
+        if matches!(self.controller.state(), UiState::Connecting | UiState::GameOver { .. }) {
+            return None;
+        }
+        let (_, moves) = self.controller.record();
+        let mut saved = SavedGame::new(self.controller.start_fen(), &moves);
+        saved.playing_white = !self.controller.player_side().is_black();
+        saved.name = self.lobby.name.clone();
+        saved.opponent_name = self.opponent_name.clone();
+        saved.chat = self.chat.lines().to_vec();
+        saved.host = self.lobby.mode == LobbyMode::Host;
+        saved.bind = Some(self.lobby.bind.clone()).filter(|_| saved.host);
+        saved.address = match self.lobby.mode {
+            LobbyMode::Host => self.lobby.port.clone(),
+            LobbyMode::Join | LobbyMode::Watch => self.lobby.address.clone(),
+        };
+        return Some(saved);
+    }
+
+    fn cancel_connecting(&mut self){
+        self.connection = None;
+        self.lobby.cancel();
+    }
+
+    fn stop_watching(&mut self){
+        self.spectating = None;
+        self.connection_lost = None;
+        self.cancel_connecting();
+    }
+
+    // What spectators of a game we host see, none before it starts.
+    fn broadcast(&self) -> Option<Broadcast>{
+        if self.lobby.mode != LobbyMode::Host || matches!(self.controller.state(), UiState::Connecting) {
+            return None;
+        }
+        let (_, moves) = self.controller.record();
+        let name = Some(self.lobby.name.trim().to_string()).filter(|name| !name.is_empty());
+        let (white, black) = match self.controller.player_side().is_black() {
+            true => (self.opponent_name.clone(), name),
+            false => (name, self.opponent_name.clone()),
+        };
+        // Game over texts are written for the players, spectators need to know who gave up.
+        let end = match self.controller.state() {
+            UiState::GameOver { reason: EndReason::OpponentForfeited } => {
+                Some(String::from(if self.controller.player_side().is_black() { "White resigned" } else { "Black resigned" }))
+            },
+            UiState::GameOver { reason } => Some(game_over_text(self.controller.board(), *reason)),
+            _ => None,
+        };
+        return Some(Broadcast { white, black, start: self.controller.start_fen(), moves: moves.iter().map(uci).collect(), end });
+    }
+
+    fn handle_network_event(&mut self, event: NetworkEvent){
+        let Some(connection) = &self.connection else {
+            return;
+        };
+        match event {
+            NetworkEvent::Connected { .. } if self.reconnect.is_some() => {
+                connection.resync(self.controller.fen(), self.controller.ply() as u16);
+                self.reconnect = Some(Reconnect::Resyncing);
+            },
+            NetworkEvent::Connected { player_side, opponent_name, fen } => {
+                let start = fen.unwrap_or(String::from(INITIAL));
+                // A resumed game goes on only if the opponent resumed it too, the host sends where it stopped.
+                if let Some(side) = self.resuming {
+                    if player_side != side || !same_position(&start, &self.controller.fen()) {
+                        self.connection = None;
+                        self.lobby.failed(String::from("Opponent is not resuming this game"));
+                        return;
+                    }
+                    self.resuming = None;
+                    // The save is written again on quit if the game is still going, so a finished game leaves none behind.
+                    if let Err(err) = fs::remove_file(&self.save_path) {
+                        println!("Could not remove {}: {}", self.save_path, err);
+                    }
+                }
+                else {
+                    // A position that doesn't load ends the game before it starts rather than playing from the wrong one.
+                    if start != INITIAL {
+                        match Setup::parse(&start).and_then(|setup| Ok((setup.to_game()?, setup))) {
+                            Ok((game, setup)) => self.start_from(game, setup),
+                            Err(err) => {
+                                self.connection = None;
+                                self.lobby.failed(format!("Bad start position: {}", err));
+                                return;
+                            },
+                        }
+                    }
+                    // A resumed game keeps the chat it was saved with.
+                    self.chat = ChatLog::new();
+                }
+                self.opponent_name = opponent_name;
+                self.controller.connected(player_side);
+            },
+            NetworkEvent::OpponentName(name) => self.opponent_name = Some(name),
+            NetworkEvent::Extensions(extensions) => self.peer_extensions = extensions,
+            NetworkEvent::TakebackRequested(takeback_to) => {
+                // Refused straight away when it can't be answered now, so the opponent isn't left waiting.
+                if !self.controller.takeback_requested(takeback_to) {
+                    connection.answer_takeback(takeback_to, false);
+                }
+            },
+            NetworkEvent::TakebackAnswered { takeback_to, accepted } => self.controller.takeback_answered(takeback_to, accepted),
+            NetworkEvent::Move(mv) => {
+                // A draw offer is acked once the player has answered it.
+                if let Some(ack) = self.controller.ack_opponent_move(&mv) {
+                    connection.send_ack(ack.ok, ack.end_state);
+                }
+            },
+            NetworkEvent::Ack(ack) => {
+                if let Some(mv) = self.pending_move.take() {
+                    self.controller.move_acked(mv, &ack);
+                }
+            },
+            NetworkEvent::Resync { fen, ply } => {
+                if self.reconnect != Some(Reconnect::Resyncing) {
+                    return;
+                }
+                self.reconnect = None;
+                match self.controller.compare_with_peer(self.pending_move, &fen, ply as usize) {
+                    ResyncOutcome::InSync => {
+                        if let Some(mv) = self.pending_move {
+                            connection.send_move(to_network_move(&mv, self.controller.offering_draw()));
+                        }
+                    },
+                    ResyncOutcome::OurMoveArrived => {
+                        if let Some(mv) = self.pending_move.take() {
+                            self.controller.apply_local_move(mv);
+                        }
+                    },
+                    ResyncOutcome::PeerBehind => {},
+                    ResyncOutcome::Diverged => {
+                        self.connection = None;
+                        self.connection_lost = Some(String::from("The games differ after reconnecting"));
+                    },
+                }
+            },
+            NetworkEvent::Lagging(lagging) => self.lagging = lagging,
+            NetworkEvent::Chat(text) => self.chat.received(&text),
+            NetworkEvent::Broadcast(broadcast) => match SpectatorView::from_broadcast(&broadcast) {
+                Ok(view) => self.spectating = Some(view),
+                Err(err) => {
+                    self.stop_watching();
+                    self.lobby.failed(format!("Can't follow the game: {}", err));
+                },
+            },
+            NetworkEvent::Disconnected(reason) => {
+                self.connection = None;
+                self.lagging = false;
+                // The last position stays up after the host leaves.
+                if self.spectating.is_some() {
+                    self.connection_lost = Some(reason);
+                }
+                else if matches!(self.controller.state(), UiState::Connecting) {
+                    self.lobby.failed(reason);
+                }
+                // Failing to get back a second time gives up.
+                else if self.reconnect != Some(Reconnect::Waiting) && self.can_reconnect() {
+                    self.reconnect();
+                }
+                else {
+                    self.reconnect = None;
+                    self.connection_lost = Some(reason);
+                }
+            },
+        }
+    }
+
+    // None before the game starts.
+    fn link_status(&self) -> Option<LinkStatus>{
+        if self.spectating.is_none() && matches!(self.controller.state(), UiState::Connecting) {
+            return None;
+        }
+        if self.connection.is_none() || self.reconnect == Some(Reconnect::Waiting) {
+            return Some(LinkStatus::Lost);
+        }
+        return Some(if self.lagging { LinkStatus::Lagging } else { LinkStatus::Connected });
+    }
+
+    // Chat stays open after the game ends, as long as the opponent is still there.
+    fn chat_available(&self) -> bool{
+        return self.connection.is_some() && self.peer_extensions.iter().any(|name| name == CHAT) && !matches!(self.controller.state(), UiState::Connecting);
+    }
+
+    fn chat_key_down(&mut self, keycode: KeyCode){
+        match keycode {
+            KeyCode::Return if self.chat_available() => {
+                if let (Some(text), Some(connection)) = (self.chat.send(), &self.connection) {
+                    connection.send_chat(text);
+                }
+            },
+            KeyCode::Back => { self.chat.draft.pop(); },
+            KeyCode::Tab | KeyCode::Escape => self.chat.toggle_open(),
+            _ => {},
+        }
+    }
+
+    // Games between two of our clients survive the connection dropping.
+    fn can_reconnect(&self) -> bool{
+        return self.peer_extensions.iter().any(|name| name == RESYNC) && !matches!(self.controller.state(), UiState::GameOver { .. });
+    }
+
+    // The host listens on the same port again and the client keeps trying to join it.
+    fn reconnect(&mut self){
+        let name = self.lobby.name.clone();
+        self.connection = Some(match self.lobby.mode {
+            LobbyMode::Host => Connection::host(self.lobby.bind.clone(), self.lobby.port.clone(), name, Some(self.controller.fen()), self.connection_timeout),
+            LobbyMode::Join => Connection::rejoin(self.lobby.address.clone(), name, self.connection_timeout),
+            LobbyMode::Watch => Connection::watch(self.lobby.address.clone(), name),
+        });
+        self.peer_extensions.clear();
+        self.reconnect = Some(Reconnect::Waiting);
+        self.broadcast_sent = None;
+    }
+
+    fn run_command(&mut self, command: Command){
+        let Some(connection) = &self.connection else {
+            return;
+        };
+        match command {
+            Command::SendMove(mv) => {
+                connection.send_move(to_network_move(&mv, false));
+                self.pending_move = Some(mv);
+            },
+            Command::OfferDraw(mv) => {
+                connection.send_move(to_network_move(&mv, true));
+                self.pending_move = Some(mv);
+            },
+            Command::AnswerDrawOffer(ack) => connection.send_ack(ack.ok, ack.end_state),
+            Command::RequestTakeback(takeback_to) => connection.request_takeback(takeback_to),
+            Command::AnswerTakeback { takeback_to, accepted } => connection.answer_takeback(takeback_to, accepted),
+        }
+    }
+
+    fn handle_input(&mut self, input: Input){
+        // Nothing can reach the opponent until the game is resynced.
+        if self.reconnect.is_some() {
+            return;
+        }
+        if let Some(command) = self.controller.handle_input(input) {
+            self.run_command(command);
+        }
+    }
+
+    // Plays the typed move if it is a legal one, otherwise it stays up for fixing.
+    fn submit_move_text(&mut self){
+        if let ParsedMove::Legal(mv) = parse_move(self.controller.board(), &self.move_text) {
+            self.move_text.clear();
+            self.handle_input(Input::MoveTyped(mv));
+        }
+    }
+
+    // Arrow keys move the cursor, starting from the selected piece or in front of the king's pawn.
+    fn move_cursor(&mut self, file_step: i32, rank_step: i32){
+        let start = match self.controller.state() {
+            UiState::PieceSelected { from, .. } => *from,
+            _ if self.controller.player_side().is_black() => 12,
+            _ => 52,
+        };
+        self.cursor = Some(match self.cursor {
+            Some(index) => step_cursor(index, file_step, rank_step),
+            None => start,
+        });
+    }
+
+    fn game_key_down(&mut self, ctx: &mut Context, keycode: KeyCode){
+        if let UiState::AwaitingPromotion { .. } = self.controller.state() {
+            if let Some(choice) = promotion_for_key(keycode) {
+                self.handle_input(Input::PromotionChosen(choice));
+            }
+            return;
+        }
+        match keycode {
+            KeyCode::F2 => self.open_analysis(),
+            KeyCode::Tab if self.chat_available() => self.chat.toggle_open(),
+            KeyCode::T if self.move_text.is_empty() => self.next_theme(ctx),
+            // Copies the arrows and marks as a PGN comment.
+            KeyCode::P if self.move_text.is_empty() && !self.annotations.is_empty() => self.copy_text(format!("{{ {} }}", self.annotations.to_pgn())),
+            KeyCode::Up => self.move_cursor(0, 1),
+            KeyCode::Down => self.move_cursor(0, -1),
+            KeyCode::Left => self.move_cursor(-1, 0),
+            KeyCode::Right => self.move_cursor(1, 0),
+            KeyCode::Back => { self.move_text.pop(); },
+            KeyCode::Escape => {
+                self.move_text.clear();
+                self.cursor = None;
+            },
+            KeyCode::Return if !self.move_text.is_empty() => self.submit_move_text(),
+            KeyCode::Return => {
+                if let Some(cursor) = self.cursor {
+                    self.handle_input(Input::SquareClicked(cursor));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // Starts analysing from the initial position in the lobby, or from the game that just ended.
+    fn open_analysis(&mut self){
+        let analysis = match self.controller.state() {
+            UiState::Connecting if self.lobby.is_editable() => AnalysisBoard::new(chess_lib::Game::new()),
+            UiState::GameOver { .. } => {
+                let (start, moves) = self.controller.record();
+                AnalysisBoard::from_game(start, &moves)
+            },
+            _ => return,
+        };
+        self.analysis = Some(analysis);
+        self.move_text.clear();
+        self.cursor = None;
+        self.annotations.clear();
+    }
+
+    fn close_analysis(&mut self){
+        self.analysis = None;
+        self.analysis_promotion = None;
+        self.move_text.clear();
+        self.annotations.clear();
+    }
+
+    fn analysis_key_down(&mut self, ctx: &mut Context, keycode: KeyCode){
+        if let Some(mut mv) = self.analysis_promotion {
+            if keycode == KeyCode::Escape {
+                self.analysis_promotion = None;
+            }
+            else if let (Some(choice), Some(analysis)) = (promotion_for_key(keycode), self.analysis.as_mut()) {
+                mv.promotion_choice = Some(choice);
+                analysis.play(mv);
+                self.analysis_promotion = None;
+            }
+            return;
+        }
+        match keycode {
+            KeyCode::F2 => return self.close_analysis(),
+            KeyCode::Escape if self.move_text.is_empty() => return self.close_analysis(),
+            KeyCode::Escape => return self.move_text.clear(),
+            KeyCode::Back => { self.move_text.pop(); return; },
+            KeyCode::T if self.move_text.is_empty() => return self.next_theme(ctx),
+            _ => {},
+        }
+        let Some(analysis) = self.analysis.as_mut() else {
+            return;
+        };
+        match keycode {
+            KeyCode::Left => analysis.back(),
+            KeyCode::Right => analysis.forward(),
+            KeyCode::Up => analysis.switch_variation(-1),
+            KeyCode::Down => analysis.switch_variation(1),
+            KeyCode::Home => analysis.to_start(),
+            KeyCode::End => analysis.to_end(),
+            KeyCode::M if self.move_text.is_empty() => analysis.promote_variation(),
+            KeyCode::Return => {
+                if let ParsedMove::Legal(mv) = parse_move(analysis.game(), &self.move_text) {
+                    self.move_text.clear();
+                    analysis.play(mv);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    // A click outside the promotion window drops the promotion, like in a game.
+    fn analysis_clicked(&mut self, x: f32, y: f32){
+        let Some(analysis) = self.analysis.as_mut() else {
+            return;
+        };
+        if let Some(mut mv) = self.analysis_promotion.take() {
+            if let Some(choice) = get_selected_promotion(x, y) {
+                mv.promotion_choice = Some(choice);
+                analysis.play(mv);
+            }
+            return;
+        }
+        if let Some(index) = board_square_at(x, y) {
+            self.analysis_promotion = analysis.square_clicked(index);
+        }
+    }
+
+    // Both players' names, the board as the host last sent it and how the game ended. There is nothing to click.
+    fn draw_spectating(&self, canvas: &mut graphics::Canvas, ctx: &Context, view: &SpectatorView) -> GameResult{
+        let theme = self.theme();
+        let game = view.game();
+        draw_board_rectangles(canvas, ctx, theme)?;
+        if let Some((from, to)) = view.last_move() {
+            draw_last_move(canvas, ctx, from, to, theme)?;
+        }
+        if let Some(king) = checked_king(game) {
+            draw_check_glow(canvas, ctx, king, theme)?;
+        }
+        draw_board_indexing(canvas, ctx, theme)?;
+        draw_board_pieces(canvas, game.get_board(), &self.piece_images, None);
+        let to_move = game.get_active_colour();
+        draw_name_plate(canvas, ctx, view.black.as_deref().unwrap_or("Black"), chess_lib::Colour::Black, to_move.is_black(), 10., theme);
+        draw_name_plate(canvas, ctx, view.white.as_deref().unwrap_or("White"), chess_lib::Colour::White, !to_move.is_black(), 670., theme);
+        draw_captured_pieces(canvas, &material(&view.start().get_board(), &game.get_board()), chess_lib::Colour::White, &self.piece_images);
+        draw_spectator_panel(canvas);
+        if let Some(status) = self.link_status() {
+            draw_link_status(canvas, ctx, status);
+        }
+        if let Some(end) = &view.end {
+            draw_game_over_window(canvas, ctx, end);
+        }
+        return Ok(());
+    }
+
+    fn draw_analysis(&self, canvas: &mut graphics::Canvas, ctx: &Context, analysis: &AnalysisBoard) -> GameResult{
+        let theme = self.theme();
+        let game = analysis.game();
+        draw_board_rectangles(canvas, ctx, theme)?;
+        if let Some((from, to)) = analysis.last_move() {
+            draw_last_move(canvas, ctx, from, to, theme)?;
+        }
+        if let Some(king) = checked_king(game) {
+            draw_check_glow(canvas, ctx, king, theme)?;
+        }
+        if let Some((from, _)) = analysis.selected() {
+            draw_selected_square(canvas, ctx, from, theme)?;
+        }
+        draw_board_indexing(canvas, ctx, theme)?;
+        draw_board_pieces(canvas, game.get_board(), &self.piece_images, None);
+        if let Some((_, targets)) = analysis.selected() {
+            draw_highlighted_squares(canvas, ctx, targets, theme)?;
+        }
+        draw_annotations(canvas, ctx, &self.annotations)?;
+        draw_move_entry(canvas, ctx, &self.move_text, &parse_move(game, &self.move_text));
+        draw_analysis_panel(canvas, analysis);
+        if self.analysis_promotion.is_some() {
+            draw_promotion_selection_window(canvas, ctx, game.get_active_colour(), &self.piece_images);
+        }
+        return Ok(());
+    }
+
+    // F3 in the lobby sets up a position, starting from the one already chosen if there is one.
+    fn open_editor(&mut self){
+        if !self.lobby.is_editable() || self.resuming.is_some() {
+            return;
+        }
+        let setup = self.lobby.fen.as_deref().and_then(|fen| Setup::parse(fen).ok()).unwrap_or(Setup::initial());
+        self.editor = Some(PositionEditor::new(setup));
+        self.editor_error = None;
+    }
+
+    fn editor_clicked(&mut self, x: f32, y: f32){
+        let Some(editor) = self.editor.as_mut() else {
+            return;
+        };
+        match get_editor_target(x, y) {
+            Some(EditorTarget::Spare(_)) | None => {},
+            Some(EditorTarget::Active) => editor.toggle_active(),
+            Some(EditorTarget::Castling(i)) => editor.toggle_castling(i),
+            Some(EditorTarget::EnPassant) => editor.cycle_en_passant(),
+            Some(EditorTarget::Clear) => editor.clear(),
+            Some(EditorTarget::Reset) => editor.reset(),
+            Some(EditorTarget::Use) => self.use_setup(false),
+            Some(EditorTarget::Analyse) => self.use_setup(true),
+        }
+    }
+
+    // Hosted games start from the set-up position, or it is opened on the analysis board.
+    // The FEN is copied to the clipboard either way.
+    fn use_setup(&mut self, analyse: bool){
+        let Some(editor) = self.editor.as_ref() else {
+            return;
+        };
+        let game = match editor.setup.to_game() {
+            Ok(game) => game,
+            Err(err) => {
+                self.editor_error = Some(err);
+                return;
+            },
+        };
+        let fen = editor.setup.to_fen();
+        self.editor = None;
+        self.copy_text(fen.clone());
+        if analyse {
+            self.analysis = Some(AnalysisBoard::new(game));
+        }
+        else {
+            // The usual start is sent as no FEN, so clients without FEN support can still join.
+            self.lobby.fen = if fen == Setup::initial().to_fen() { None } else { Some(fen) };
+        }
+    }
+
+    fn editor_mouse_down(&mut self, x: f32, y: f32){
+        let Some(editor) = self.editor.as_mut() else {
+            return;
+        };
+        self.editor_error = None;
+        if let Some(square) = board_square_at(x, y) {
+            editor.pick_up_square(square);
+        }
+        else if let Some(EditorTarget::Spare(piece)) = get_editor_target(x, y) {
+            editor.pick_up_spare(piece);
+        }
+    }
+
+    fn editor_key_down(&mut self, ctx: &mut Context, keycode: KeyCode){
+        match keycode {
+            KeyCode::Escape | KeyCode::F3 => self.editor = None,
+            KeyCode::Return => self.use_setup(false),
+            KeyCode::T => self.next_theme(ctx),
+            _ => {},
+        }
+    }
+
+    // FEN of whatever is on screen: the analysis board, the setup, the game or the position the lobby will host.
+    fn current_fen(&self) -> Option<String>{
+        if let Some(view) = &self.spectating {
+            return Some(Setup::from_game(view.game()).to_fen());
+        }
+        if let Some(analysis) = &self.analysis {
+            return Some(Setup::from_game(analysis.game()).to_fen());
+        }
+        if let Some(editor) = &self.editor {
+            return Some(editor.setup.to_fen());
+        }
+        return match self.controller.state() {
+            UiState::Connecting => self.lobby.fen.clone(),
+            _ => Some(self.controller.fen()),
+        };
+    }
+
+    fn copy_fen(&mut self){
+        if let Some(fen) = self.current_fen() {
+            self.copy_text(fen);
+        }
+    }
+
+    fn copy_text(&mut self, text: String){
+        let result = match self.clipboard.as_mut() {
+            Some(clipboard) => clipboard.set_text(text).map_err(|err| err.to_string()),
+            None => Err(String::from("No clipboard available")),
+        };
+        if let Err(err) = result {
+            self.clipboard_error = Some(format!("Could not copy: {}", err));
+        }
+    }
+
+    // A pasted FEN replaces the setup in the editor, and is opened on the analysis
+    // board from the lobby or analysis. Games in progress keep their position.
+    fn paste_fen(&mut self){
+        let in_lobby = matches!(self.controller.state(), UiState::Connecting) && self.lobby.is_editable();
+        if self.editor.is_none() && self.analysis.is_none() && !in_lobby {
+            return;
+        }
+        let text = match self.clipboard.as_mut().map(|clipboard| clipboard.get_text()) {
+            Some(Ok(text)) => text,
+            Some(Err(err)) => {
+                self.clipboard_error = Some(format!("Could not paste: {}", err));
+                return;
+            },
+            None => {
+                self.clipboard_error = Some(String::from("No clipboard available"));
+                return;
+            },
+        };
+        let setup = match Setup::parse(text.trim()) {
+            Ok(setup) => setup,
+            Err(err) => {
+                self.clipboard_error = Some(format!("Not a valid FEN: {}", err));
+                return;
+            },
+        };
+        // The editor takes positions that aren't legal yet so they can be fixed there.
+        if let Some(editor) = self.editor.as_mut() {
+            editor.setup = setup;
+            return;
+        }
+        match setup.to_game() {
+            Ok(game) => {
+                self.analysis = Some(AnalysisBoard::new(game));
+                self.analysis_promotion = None;
+                self.move_text.clear();
+                self.annotations.clear();
+            },
+            Err(err) => self.clipboard_error = Some(format!("Not a valid FEN: {}", err)),
+        }
+    }
+
+    // Draws what goes over every screen and shows the frame.
+    fn finish_frame(&self, mut canvas: graphics::Canvas, ctx: &mut Context) -> GameResult{
+        if let Some(error) = &self.clipboard_error {
+            draw_message_window(&mut canvas, ctx, error, "Click to close");
+        }
+        return canvas.finish(ctx);
+    }
+
+    // Right-click cancels premoves if there are any, otherwise it marks the
+    // square or draws an arrow from where the drag started.
+    fn right_clicked(&mut self, ctx: &Context, x: f32, y: f32){
+        let start = self.annotation_start.take();
+        let in_game = self.analysis.is_none();
+        if in_game && matches!(self.controller.state(), UiState::Connecting) {
+            return;
+        }
+        if in_game && (!self.controller.premoves().is_empty() || matches!(self.controller.state(), UiState::PremoveSelected { .. })) {
+            self.handle_input(Input::PremovesCancelled);
+            return;
+        }
+        let (Some(from), Some(to)) = (start, board_square_at(x, y)) else {
+            return;
+        };
+        let mods = ctx.keyboard.active_mods();
+        let colour = MarkColour::from_modifiers(mods.contains(KeyMods::SHIFT), mods.contains(KeyMods::ALT), mods.contains(KeyMods::CTRL));
+        if from == to {
+            self.annotations.toggle_mark(from, colour);
+        }
+        else {
+            self.annotations.toggle_arrow(from, to, colour);
+        }
+    }
+
+    // Only peers that announced `TAKEBACK` are asked.
+    fn takeback_available(&self) -> bool{
+        return self.peer_extensions.iter().any(|name| name == TAKEBACK) && self.controller.can_request_takeback();
+    }
+
+    // What a click at (x, y) means in the current state, if anything.
+    fn input_at(&self, x: f32, y: f32) -> Option<Input>{
+        if self.takeback_available() && Rect::new(745., 560., 140., 40.).contains(Point2{x, y}) {
+            return Some(Input::TakebackRequested);
+        }
+        return match self.controller.state() {
+            UiState::Idle | UiState::PieceSelected { .. } => {
+                if Rect::new(743., 306., 141., 52.).contains(Point2{x, y}) {
+                    Some(Input::DrawRequested)
+                }
+                else {
+                    board_square_at(x, y).map(Input::SquareClicked)
+                }
+            },
+            UiState::AwaitingPromotion { .. } => get_selected_promotion(x, y).map(Input::PromotionChosen),
+            UiState::DrawOffered => get_offer_answer(x, y).map(Input::DrawOfferAnswered),
+            UiState::TakebackOffered { .. } => get_offer_answer(x, y).map(Input::TakebackAnswered),
+            UiState::WaitingForOpponent | UiState::PremoveSelected { .. } => board_square_at(x, y).map(Input::SquareClicked),
+            UiState::Connecting | UiState::GameOver { .. } => None,
+        }
+    }
+
+    fn lobby_clicked(&mut self, x: f32, y: f32){
+        match get_lobby_target(x, y) {
+            Some(LobbyTarget::Mode(mode)) => self.lobby.set_mode(mode),
+            Some(LobbyTarget::Field(row)) => {
+                if let Some(field) = self.lobby.fields().get(row) {
+                    self.lobby.focus(*field);
+                }
+            },
+            Some(LobbyTarget::Start) => {
+                if self.lobby.is_editable() {
+                    self.start_connecting();
+                }
+                else {
+                    self.cancel_connecting();
+                }
+            },
+            None => {},
+        }
+    }
+}
+
+// CHESS_TIMEOUT_SECS sets how long an opponent that sends heartbeats can go quiet before the connection is dropped.
+fn connection_timeout() -> Duration{
+    return match env::var("CHESS_TIMEOUT_SECS").ok().and_then(|secs| secs.parse().ok()) {
+        Some(secs) => Duration::from_secs(secs),
+        None => network::DEFAULT_TIMEOUT,
+    };
+}
+
+// CHESS_ANIMATION_MS sets how long a move takes to slide, 0 turns it off.
+fn animation_duration() -> Duration{
+    return match env::var("CHESS_ANIMATION_MS").ok().and_then(|ms| ms.parse().ok()) {
+        Some(ms) => Duration::from_millis(ms),
+        None => animation::DEFAULT_DURATION,
+    };
 }
 
 fn get_pos_index(x: f32, y: f32) -> usize{
@@ -132,6 +1034,121 @@ fn get_selected_promotion(x: f32, y: f32) -> Option<chess_lib::PieceType>{
     return None;
 }
 
+#[derive(PartialEq)]
+enum Reconnect {
+    // For the opponent to come back, or for us to get back to the host.
+    Waiting,
+    // Connected again, waiting for the peer's `Resync`.
+    Resyncing,
+}
+
+enum EditorTarget {
+    Spare(char),
+    Active,
+    Castling(usize),
+    EnPassant,
+    Clear,
+    Reset,
+    Use,
+    Analyse,
+}
+
+// Matches the layout in `draw_editor`.
+fn get_editor_target(x: f32, y: f32) -> Option<EditorTarget>{
+    for (row, letters) in PALETTE.iter().enumerate(){
+        for (column, letter) in letters.iter().enumerate(){
+            if(Rect::new(728. + column as f32 * 28., 20. + row as f32 * 34., 28., 32.).contains(Point2{x, y})){
+                return Some(EditorTarget::Spare(*letter));
+            }
+        }
+    }
+    if(Rect::new(725., 100., 170., 32.).contains(Point2{x, y})){
+        return Some(EditorTarget::Active);
+    }
+    for i in 0..4{
+        if(Rect::new(725. + i as f32 * 43., 170., 38., 32.).contains(Point2{x, y})){
+            return Some(EditorTarget::Castling(i));
+        }
+    }
+    if(Rect::new(725., 220., 170., 32.).contains(Point2{x, y})){
+        return Some(EditorTarget::EnPassant);
+    }
+    if(Rect::new(725., 270., 80., 32.).contains(Point2{x, y})){
+        return Some(EditorTarget::Clear);
+    }
+    if(Rect::new(815., 270., 80., 32.).contains(Point2{x, y})){
+        return Some(EditorTarget::Reset);
+    }
+    if(Rect::new(745., 500., 140., 40.).contains(Point2{x, y})){
+        return Some(EditorTarget::Use);
+    }
+    if(Rect::new(745., 560., 140., 40.).contains(Point2{x, y})){
+        return Some(EditorTarget::Analyse);
+    }
+    return None;
+}
+
+fn promotion_for_key(keycode: KeyCode) -> Option<chess_lib::PieceType>{
+    return match keycode {
+        KeyCode::Q => Some(chess_lib::PieceType::Queen),
+        KeyCode::R => Some(chess_lib::PieceType::Rook),
+        KeyCode::B => Some(chess_lib::PieceType::Bishop),
+        KeyCode::N => Some(chess_lib::PieceType::Knight),
+        _ => None,
+    };
+}
+
+fn get_offer_answer(x: f32, y: f32) -> Option<bool>{
+    if(Rect::new(255., 330., 100., 40.).contains(Point2{x, y})){
+        return Some(true);
+    }
+    if(Rect::new(385., 330., 100., 40.).contains(Point2{x, y})){
+        return Some(false);
+    }
+    return None;
+}
+
+fn board_square_at(x: f32, y: f32) -> Option<usize>{
+    if x < 0. || y < 0. || x >= 720. || y >= 720. {
+        return None;
+    }
+    return Some(get_pos_index(x, y));
+}
+
+// Moves a board index by whole files and ranks, stopping at the edge. Up is towards rank 8.
+fn step_cursor(index: usize, file_step: i32, rank_step: i32) -> usize{
+    let file = (index as i32 % 8 + file_step).clamp(0, 7);
+    let row = (index as i32 / 8 - rank_step).clamp(0, 7);
+    return (row * 8 + file) as usize;
+}
+
+enum LobbyTarget {
+    Mode(LobbyMode),
+    Field(usize),
+    Start,
+}
+
+fn get_lobby_target(x: f32, y: f32) -> Option<LobbyTarget>{
+    if(Rect::new(190., 150., 100., 44.).contains(Point2{x, y})){
+        return Some(LobbyTarget::Mode(LobbyMode::Host));
+    }
+    if(Rect::new(310., 150., 100., 44.).contains(Point2{x, y})){
+        return Some(LobbyTarget::Mode(LobbyMode::Join));
+    }
+    if(Rect::new(430., 150., 100., 44.).contains(Point2{x, y})){
+        return Some(LobbyTarget::Mode(LobbyMode::Watch));
+    }
+    for row in 0..3{
+        if(Rect::new(190., 255. + row as f32 * 70., 340., 40.).contains(Point2{x, y})){
+            return Some(LobbyTarget::Field(row));
+        }
+    }
+    if(Rect::new(190., 445., 340., 48.).contains(Point2{x, y})){
+        return Some(LobbyTarget::Start);
+    }
+    return None;
+}
+
 impl MainState{
     fn is_click(&mut self, x: f32, y: f32) -> bool{
         if(x == self.mouse_down_x && y == self.mouse_down_y && !self.last_click_time.elapsed().as_millis() < 50){
@@ -143,7 +1160,22 @@ impl MainState{
 }
 
 impl event::EventHandler<ggez::GameError> for MainState {
-    fn update(&mut self, _ctx: &mut Context) -> GameResult {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        while let Some(event) = self.connection.as_ref().and_then(|connection| connection.poll()) {
+            self.handle_network_event(event);
+        }
+        // Sent right after the ack for the opponent's move, so it goes out the same frame.
+        if let Some(command) = self.controller.take_premove().filter(|_| self.reconnect.is_none()) {
+            self.run_command(command);
+        }
+        // Spectators only hear about changes.
+        if let Some(broadcast) = self.broadcast().filter(|broadcast| self.broadcast_sent.as_ref() != Some(broadcast)) {
+            if let Some(connection) = &self.connection {
+                connection.broadcast(broadcast.clone());
+            }
+            self.broadcast_sent = Some(broadcast);
+        }
+        self.update_animation(ctx.time.delta());
         Ok(())
     }
     fn mouse_button_down_event(
@@ -153,75 +1185,161 @@ impl event::EventHandler<ggez::GameError> for MainState {
         x: f32,
         y: f32,
     ) -> GameResult {
+        let (x, y) = self.viewport.to_layout(x, y);
+        if button == MouseButton::Right {
+            self.annotation_start = board_square_at(x, y);
+            return Ok(());
+        }
+        if self.editor.is_some() {
+            self.editor_mouse_down(x, y);
+            return Ok(());
+        }
         self.mouse_down_x = x;
         self.mouse_down_y = y;
         Ok(())
     }
     fn mouse_button_up_event(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         button: MouseButton,
         x: f32,
         y: f32,
     ) -> GameResult {
-        self.mouse_down = false;
-        if self.is_click(x, y){ // click
-            if(self.awaiting_promotion_choice){
-                let mut promotion_choice = get_selected_promotion(x, y);
-                if(!promotion_choice.is_none()){
-                    let mut mv = self.move_to_make_after_promotion.unwrap();
-                    mv.promotion_choice = promotion_choice;
-                    let (ok, gamestate) = do_move(&mut self.stream, mv, None);
-                    if(self.player_side == chess_lib::Colour::White || ok){
-                        self.board.make_move(mv);
-                        let _Move = await_move(&mut self.stream); // opponent move
-                        let result = self.board.make_move(Move::new(&self.board, Position::new(_Move.from.1 as usize, _Move.from.0 as usize).unwrap(), Position::new(_Move.to.1 as usize, _Move.to.0 as usize).unwrap()).unwrap());
-                        send_ack(&mut self.stream, result.is_err(), chess_lib_state_to_network_state(self.board.get_game_state()));
-                    }
-                    self.move_to_make_after_promotion = None;
-                    self.awaiting_promotion_choice = false;
-                }
+        let (x, y) = self.viewport.to_layout(x, y);
+        if self.clipboard_error.take().is_some() || self.spectating.is_some() {
+            return Ok(());
+        }
+        if let Some(editor) = self.editor.as_mut() {
+            // Pieces are dragged off the board with the left button or removed with the right one.
+            match (button, board_square_at(x, y)) {
+                (MouseButton::Right, Some(square)) => editor.remove(square),
+                (MouseButton::Left, square) if editor.held().is_some() => editor.drop(square),
+                (MouseButton::Left, _) => self.editor_clicked(x, y),
+                _ => {},
             }
-            else if(self.board.get_active_colour() == self.player_side){
-                let index = get_pos_index(x, y);
-                if(Rect::new(743., 306., 141., 52.).contains(Point2{x, y})){
-                    self.board.submit_draw();
-                }
-                if(x > 700.){
-                    return Ok(());
-                }
-                if(self.highlighted_movements.is_none()){
-                    let selectedPiece = self.board.get_board()[index];
-                    if(!selectedPiece.is_none() && selectedPiece.unwrap().colour == self.board.get_active_colour()){
-                        let mut positions = Vec::new();
-                        for square in self.board.get_legal_moves_from(chess_lib::Position::new_from_idx(index).unwrap()).as_ref().unwrap().iter(){
-                            positions.push(square.to.idx as u32);
-                        }
-                        self.highlighted_movements = Some(positions);
-                        self.selected_piece_index = Some(index);
-                    }
-                }
-                else{
-                    if(self.highlighted_movements.as_ref().unwrap().iter().any(|&a| a == index as u32)){
-                        let mv = Move::new(&self.board, Position::new_from_idx(self.selected_piece_index.unwrap()).unwrap(), Position::new_from_idx(index).unwrap()).unwrap();
-                        if(mv.is_promotion()){
-                            self.awaiting_promotion_choice = true;
-                            self.move_to_make_after_promotion = Some(mv);
-                        }
-                        else{
-                            let (ok, gamestate) = do_move(&mut self.stream, mv, None);
-                            if(self.player_side == chess_lib::Colour::White || ok){
-                                self.board.make_move(Move::new(&self.board, Position::new_from_idx(self.selected_piece_index.unwrap()).unwrap(), Position::new_from_idx(index).unwrap()).unwrap());
-                                let _Move = await_move(&mut self.stream); // opponent move
-                                let result = self.board.make_move(Move::new(&self.board, Position::new(_Move.from.1 as usize, _Move.from.0 as usize).unwrap(), Position::new(_Move.to.1 as usize, _Move.to.0 as usize).unwrap()).unwrap());
-                                send_ack(&mut self.stream, !result.is_err(), chess_lib_state_to_network_state(self.board.get_game_state()));
-                            }
-                        }
-                    }
-                    self.highlighted_movements = None;
-                    self.selected_piece_index = None;
-                }
+            return Ok(());
+        }
+        if button == MouseButton::Right {
+            self.right_clicked(ctx, x, y);
+            return Ok(());
+        }
+        if !self.is_click(x, y){
+            return Ok(());
+        }
+        if self.analysis.is_some() {
+            self.annotations.clear();
+            self.analysis_clicked(x, y);
+            return Ok(());
+        }
+        if matches!(self.controller.state(), UiState::Connecting) {
+            self.lobby_clicked(x, y);
+            return Ok(());
+        }
+        self.annotations.clear();
+        if matches!(self.controller.state(), UiState::GameOver { .. }) && Rect::new(745., 560., 140., 40.).contains(Point2{x, y}) {
+            self.open_analysis();
+            return Ok(());
+        }
+        // The open chat covers the side panel buttons.
+        if self.chat.is_open() && x >= 720. {
+            if Rect::new(815., 174., 78., 28.).contains(Point2{x, y}) {
+                self.chat.toggle_mute();
+            }
+            return Ok(());
+        }
+        if let Some(input) = self.input_at(x, y) {
+            self.handle_input(input);
+        }
+        Ok(())
+    }
+
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        if let Some(saved) = self.saved_game() {
+            match saved.to_toml().and_then(|text| fs::write(&self.save_path, text).map_err(|err| err.to_string())) {
+                Ok(()) => println!("Game saved to {}, continue it with `resume`", self.save_path),
+                Err(err) => println!("Could not save the game: {}", err),
+            }
+        }
+        Ok(false)
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) -> GameResult {
+        self.viewport = Viewport::fit(width, height);
+        let piece_pixels = self.viewport.pixels(PIECE_SIZE);
+        if piece_pixels != self.piece_pixels {
+            self.piece_pixels = piece_pixels;
+            self.piece_images = load_piece_images(ctx, self.theme(), piece_pixels);
+        }
+        Ok(())
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, character: char) -> GameResult {
+        if self.editor.is_some() {
+            return Ok(());
+        }
+        if self.analysis.is_some() {
+            if is_move_char(character) && self.move_text.len() < 8 && self.analysis_promotion.is_none() {
+                self.move_text.push(character);
+            }
+        }
+        else if matches!(self.controller.state(), UiState::Connecting) {
+            self.lobby.type_char(character);
+        }
+        else if self.chat.is_open() {
+            self.chat.type_char(character);
+        }
+        // While choosing a promotion the letters pick the piece instead.
+        else if is_move_char(character) && self.move_text.len() < 8 && !matches!(self.controller.state(), UiState::AwaitingPromotion { .. }) {
+            self.move_text.push(character);
+        }
+        Ok(())
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, input: KeyInput, _repeated: bool) -> GameResult {
+        if self.clipboard_error.take().is_some() {
+            return Ok(());
+        }
+        if input.mods.contains(KeyMods::CTRL) {
+            match input.keycode {
+                Some(KeyCode::C) => { self.copy_fen(); return Ok(()); },
+                Some(KeyCode::V) => { self.paste_fen(); return Ok(()); },
+                _ => {},
+            }
+        }
+        if self.spectating.is_some() {
+            if input.keycode == Some(KeyCode::Escape) {
+                self.stop_watching();
+            }
+            return Ok(());
+        }
+        if self.analysis.is_some() {
+            if let Some(keycode) = input.keycode {
+                self.analysis_key_down(ctx, keycode);
+            }
+            return Ok(());
+        }
+        if self.editor.is_some() {
+            if let Some(keycode) = input.keycode {
+                self.editor_key_down(ctx, keycode);
+            }
+            return Ok(());
+        }
+        if !matches!(self.controller.state(), UiState::Connecting) {
+            match input.keycode {
+                Some(keycode) if self.chat.is_open() => self.chat_key_down(keycode),
+                Some(keycode) => self.game_key_down(ctx, keycode),
+                None => {},
             }
+            return Ok(());
+        }
+        match input.keycode {
+            Some(KeyCode::Back) => self.lobby.backspace(),
+            Some(KeyCode::Tab) => self.lobby.focus_next(),
+            Some(KeyCode::Return) if self.lobby.is_editable() => self.start_connecting(),
+            Some(KeyCode::Escape) if !self.lobby.is_editable() => self.cancel_connecting(),
+            Some(KeyCode::F2) => self.open_analysis(),
+            Some(KeyCode::F3) => self.open_editor(),
+            _ => {},
         }
         Ok(())
     }
@@ -231,39 +1349,110 @@ impl event::EventHandler<ggez::GameError> for MainState {
             ctx,
             graphics::Color::from([0.1, 0.2, 0.3, 1.0]),
         );
+        let [x, y, w, h] = self.viewport.screen_coordinates();
+        canvas.set_screen_coordinates(Rect::new(x, y, w, h));
+        if let Some(view) = &self.spectating {
+            self.draw_spectating(&mut canvas, ctx, view)?;
+            return self.finish_frame(canvas, ctx);
+        }
+        if let Some(analysis) = &self.analysis {
+            self.draw_analysis(&mut canvas, ctx, analysis)?;
+            return self.finish_frame(canvas, ctx);
+        }
+        if let Some(editor) = &self.editor {
+            let theme = self.theme();
+            draw_board_rectangles(&mut canvas, ctx, theme)?;
+            draw_board_indexing(&mut canvas, ctx, theme)?;
+            let mouse = ctx.mouse.position();
+            let (mouse_x, mouse_y) = self.viewport.to_layout(mouse.x, mouse.y);
+            draw_editor(&mut canvas, ctx, editor, &self.piece_images, self.editor_error.as_deref(), Vec2::new(mouse_x, mouse_y), theme);
+            return self.finish_frame(canvas, ctx);
+        }
 
-        draw_board_rectangles(&mut canvas, &ctx);
-        draw_board_indexing(&mut canvas, &ctx);
-        draw_board_pieces(&mut canvas, self.board.get_board(), &self.piece_images);
-        if(!self.highlighted_movements.is_none()){ 
-            draw_highlighted_squares(&mut canvas, &ctx, self.highlighted_movements.as_ref().unwrap()); 
+        let theme = self.theme();
+        draw_board_rectangles(&mut canvas, &ctx, theme);
+        if let Some((from, to)) = self.controller.last_move() {
+            draw_last_move(&mut canvas, ctx, from, to, theme)?;
+        }
+        if let Some(king) = self.controller.checked_king() {
+            draw_check_glow(&mut canvas, ctx, king, theme)?;
         }
-        if(self.board.get_game_state() == GameState::GameOver){ 
-            draw_game_over_window(&mut canvas, ctx,  self.board.get_game_over_reason().unwrap(), self.board.get_active_colour()); 
+        draw_premoves(&mut canvas, ctx, self.controller.premoves(), theme)?;
+        if let UiState::PieceSelected { from, .. } | UiState::PremoveSelected { from } = self.controller.state() {
+            draw_selected_square(&mut canvas, ctx, *from, theme)?;
         }
-        if(self.awaiting_promotion_choice){ 
-            draw_promotion_selection_window(&mut canvas, ctx, self.board.get_active_colour(), &self.piece_images); 
+        if let Some(cursor) = self.cursor {
+            draw_cursor(&mut canvas, ctx, cursor, theme)?;
+        }
+        draw_board_indexing(&mut canvas, &ctx, theme);
+        let board = self.controller.board();
+        draw_board_pieces(&mut canvas, board.get_board(), &self.piece_images, self.animation.as_ref());
+        draw_annotations(&mut canvas, ctx, &self.annotations)?;
+        match self.controller.state() {
+            UiState::Connecting => draw_lobby(&mut canvas, ctx, &self.lobby, theme),
+            UiState::Idle => {},
+            UiState::PieceSelected { targets, .. } => { draw_highlighted_squares(&mut canvas, &ctx, targets, theme); },
+            UiState::AwaitingPromotion { .. } => draw_promotion_selection_window(&mut canvas, ctx, board.get_active_colour(), &self.piece_images),
+            UiState::WaitingForOpponent | UiState::PremoveSelected { .. } => draw_status_text(&mut canvas, "Opponent's turn"),
+            UiState::DrawOffered => draw_offer_window(&mut canvas, ctx, "Opponent offers a draw"),
+            UiState::TakebackOffered { .. } => draw_offer_window(&mut canvas, ctx, "Allow a takeback?"),
+            UiState::GameOver { reason } => draw_game_over_window(&mut canvas, ctx, &game_over_text(board, *reason)),
+        }
+        if !matches!(self.controller.state(), UiState::Connecting) {
+            let player_side = self.controller.player_side();
+            let opponent_side = if player_side.is_black() { chess_lib::Colour::White } else { chess_lib::Colour::Black };
+            let to_move = board.get_active_colour();
+            let player_name = if self.lobby.name.trim().is_empty() { "You" } else { self.lobby.name.trim() };
+            draw_name_plate(&mut canvas, ctx, self.opponent_name.as_deref().unwrap_or("Opponent"), opponent_side, to_move == opponent_side, 10., theme);
+            draw_name_plate(&mut canvas, ctx, player_name, player_side, to_move == player_side, 670., theme);
+            draw_move_entry(&mut canvas, ctx, &self.move_text, &parse_move(board, &self.move_text));
+        }
+        draw_request_draw_button(&mut canvas, ctx, if self.controller.offering_draw() { "Draw offered" } else { "Request draw" });
+        if !matches!(self.controller.state(), UiState::Connecting) {
+            draw_captured_pieces(&mut canvas, &material(&self.controller.start().get_board(), &board.get_board()), self.controller.player_side(), &self.piece_images);
+        }
+        if self.takeback_available() {
+            draw_side_button(&mut canvas, ctx, "Takeback", 560.);
+        }
+        if let UiState::GameOver { .. } = self.controller.state() {
+            draw_side_button(&mut canvas, ctx, "Analyse", 560.);
+        }
+        if self.controller.pending_takeback().is_some() {
+            draw_status_text(&mut canvas, "Takeback asked");
+        }
+        if self.chat.is_open() {
+            draw_chat(&mut canvas, ctx, &self.chat, theme);
+        }
+        else if self.chat_available() {
+            draw_chat_hint(&mut canvas, self.chat.unread());
+        }
+        if let Some(status) = self.link_status() {
+            draw_link_status(&mut canvas, ctx, status);
+        }
+        match (&self.reconnect, self.lobby.mode) {
+            (Some(Reconnect::Waiting), LobbyMode::Host) => draw_message_window(&mut canvas, ctx, "Opponent disconnected, waiting for them to reconnect", "The game resumes when they are back"),
+            (Some(Reconnect::Waiting), LobbyMode::Join | LobbyMode::Watch) => draw_message_window(&mut canvas, ctx, "Connection lost, reconnecting", "The game resumes when the host is back"),
+            (Some(Reconnect::Resyncing), _) => draw_message_window(&mut canvas, ctx, "Reconnected, checking the position", ""),
+            (None, _) => {},
         }
-        draw_request_draw_button(&mut canvas, ctx);
 
-        canvas.finish(ctx)?;
-        Ok(())
+        self.finish_frame(canvas, ctx)
     }
 }
 
 pub fn main() -> GameResult {
     let window_mode = ggez::conf::WindowMode{
-        width: 900.0,
-        height: 720.0,
+        width: viewport::WIDTH,
+        height: viewport::HEIGHT,
         maximized: false,
         fullscreen_type: FullscreenType::Windowed,
         transparent: false,
         borderless: false,
-        min_height: 720.0,
-        min_width: 720.0,
-        max_height: 720.0,
-        max_width: 720.0,
-        resizable: false,
+        min_height: viewport::HEIGHT / 2.,
+        min_width: viewport::WIDTH / 2.,
+        max_height: 0.0,
+        max_width: 0.0,
+        resizable: true,
         visible: true,
         resize_on_scale_factor_change: false,
         logical_size: None};
@@ -0,0 +1,123 @@
+use chess_lib::{Colour, Piece};
+
+// Piece letters as returned by `Piece::to_char`, most valuable first.
+const PIECE_LETTERS: [char; 6] = ['Q', 'R', 'B', 'N', 'P', 'K'];
+
+pub fn piece_value(piece: char) -> i32{
+    return match piece {
+        'P' => 1,
+        'N' | 'B' => 3,
+        'R' => 5,
+        'Q' => 9,
+        _ => 0,
+    };
+}
+
+// Captured pieces per side, as piece letters ordered from most to least valuable.
+pub struct Material {
+    // Black pieces taken by white, and the other way around.
+    pub captured_by_white: Vec<char>,
+    pub captured_by_black: Vec<char>,
+    // Positive when white is ahead.
+    pub balance: i32,
+}
+
+impl Material {
+    pub fn captured_by(&self, colour: Colour) -> &Vec<char>{
+        return if colour.is_black() { &self.captured_by_black } else { &self.captured_by_white };
+    }
+
+    // Material advantage of `colour`, negative when behind.
+    pub fn advantage(&self, colour: Colour) -> i32{
+        return if colour.is_black() { -self.balance } else { self.balance };
+    }
+}
+
+fn count(board: &[Option<Piece>; 64], colour: Colour, letter: char) -> usize{
+    return board.iter().filter(|piece| match piece {
+        Some(piece) => piece.colour == colour && piece.to_char() == letter,
+        None => false,
+    }).count();
+}
+
+// Works out what each side is missing compared to the position the game started
+// from. Pieces beyond the starting count came from promotions, so they account
+// for missing pawns instead of being counted as captures.
+fn missing(start: &[Option<Piece>; 64], board: &[Option<Piece>; 64], colour: Colour) -> Vec<char>{
+    let mut promoted = 0;
+    let mut missing = Vec::new();
+    for letter in PIECE_LETTERS {
+        let start = count(start, colour, letter);
+        let on_board = count(board, colour, letter);
+        promoted += on_board.saturating_sub(start);
+        let gone = match letter {
+            'P' => start.saturating_sub(on_board).saturating_sub(promoted),
+            _ => start.saturating_sub(on_board),
+        };
+        missing.extend(std::iter::repeat(letter).take(gone));
+    }
+    return missing;
+}
+
+pub fn material(start: &[Option<Piece>; 64], board: &[Option<Piece>; 64]) -> Material{
+    let balance = board.iter().flatten().map(|piece| match piece.colour {
+        Colour::White => piece_value(piece.to_char()),
+        Colour::Black => -piece_value(piece.to_char()),
+    }).sum();
+    return Material {
+        captured_by_white: missing(start, board, Colour::Black),
+        captured_by_black: missing(start, board, Colour::White),
+        balance,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chess_lib::{Game, Move, Position};
+    use crate::test_support::{board_after, square};
+
+    #[test]
+    fn starting_position_is_even() {
+        let start = Game::new().get_board();
+        let material = material(&start, &start);
+        assert!(material.captured_by_white.is_empty());
+        assert!(material.captured_by_black.is_empty());
+        assert_eq!(material.balance, 0);
+    }
+
+    #[test]
+    fn captures_are_listed_by_value() {
+        // 1.e4 d5 2.exd5 Qxd5 3.Nc3 Qxa2 4.Rxa2
+        let board = board_after(&[("e2", "e4"), ("d7", "d5"), ("e4", "d5"), ("d8", "d5"), ("b1", "c3"), ("d5", "a2"), ("a1", "a2")]);
+        let material = material(&Game::new().get_board(), &board);
+        assert_eq!(material.captured_by_white, vec!['Q', 'P']);
+        assert_eq!(material.captured_by_black, vec!['P', 'P']);
+        assert_eq!(material.balance, 8);
+        assert_eq!(material.advantage(Colour::Black), -8);
+    }
+
+    #[test]
+    fn promotion_is_not_a_capture() {
+        let board = board_after(&[("h2", "h4"), ("g7", "g5"), ("h4", "g5"), ("h7", "h6"), ("g5", "h6"), ("f8", "g7"), ("h6", "g7"), ("g8", "f6"), ("g7", "h8")]);
+        let material = material(&Game::new().get_board(), &board);
+        // White lost one pawn to promotion and nothing to captures.
+        assert!(material.captured_by_black.is_empty());
+        assert_eq!(material.captured_by_white, vec!['R', 'B', 'P', 'P']);
+        // Rook, bishop and two pawns taken, plus a pawn turned into a queen.
+        assert_eq!(material.balance, 10 + 8);
+    }
+
+    #[test]
+    fn pieces_missing_from_the_start_are_not_captures() {
+        // White starts without the queen's rook and knight, black without its queen.
+        let start = Game::from_fen("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/2BQKBNR w Kkq - 0 1").unwrap();
+        let mv = Move::new(&start, Position::new_from_idx(square("e2")).unwrap(), Position::new_from_idx(square("e4")).unwrap()).unwrap();
+        let mut game = start.clone();
+        game.make_move(mv).unwrap();
+        let material = material(&start.get_board(), &game.get_board());
+        assert!(material.captured_by_white.is_empty());
+        assert!(material.captured_by_black.is_empty());
+        assert_eq!(material.balance, 9 - 5 - 3);
+    }
+}
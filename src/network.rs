@@ -1,19 +1,182 @@
-use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, string, thread};
-use rmp_serde::{Deserializer, Serializer};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::de::{DeserializeOwned, IgnoredAny};
 use serde::{Deserialize, Serialize};
-use chess_networking::{self, Ack};
+use chess_lib::{Position, PieceType};
+use chess_networking::{self, Ack, PromotionPiece};
 
-const LOCAL_HOST: &str = "127.0.0.1";
-pub fn chess_lib_state_to_network_state(state: chess_lib::GameState) -> Option<chess_networking::GameState>{
-    return match state{
+// Hosting listens on every IPv4 interface unless told otherwise, so players on other machines can join.
+pub const DEFAULT_BIND: &str = "0.0.0.0";
+// How often a client tries to get back to a host it lost.
+const RETRY_INTERVAL: Duration = Duration::from_secs(1);
+// Peers that announced `HEARTBEAT` get one this often while nothing else is sent.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(1);
+// Hearing nothing for this long means the connection is lagging.
+const LAG_AFTER: Duration = Duration::from_secs(3);
+// Hearing nothing for this long from a peer that sends heartbeats drops the connection.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+// How long the host waits for a new connection to say it is a spectator before taking it for the opponent.
+const SPECTATE_WAIT: Duration = Duration::from_millis(250);
+// A spectator that can't take an update this fast is dropped rather than holding up the others.
+const SPECTATOR_WRITE_TIMEOUT: Duration = Duration::from_secs(2);
+
+// A message we are waiting for, or anything else the peer sent in the meantime.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Expected<T> {
+    Wanted(T),
+    Skipped(IgnoredAny),
+}
+
+// Reads exactly one msgpack value per message, so messages arriving in the same
+// TCP segment are not lost. Messages of another type are skipped like before.
+pub fn read_message<T: DeserializeOwned, R: Read>(stream: &mut R) -> io::Result<T>{
+    loop {
+        match rmp_serde::from_read::<_, Expected<T>>(&mut *stream){
+            Ok(Expected::Wanted(message)) => return Ok(message),
+            Ok(Expected::Skipped(_)) => {},
+            Err(err) => return Err(decode_error_to_io(err)),
+        }
+    }
+}
+
+// Protocol extensions this client understands, announced to the peer right after
+// the handshake. Peers that don't know the message skip it and never announce
+// anything back, so we never send them extension messages either.
+pub const EXTENSIONS: [&str; 4] = [TAKEBACK, RESYNC, HEARTBEAT, CHAT];
+pub const TAKEBACK: &str = "takeback";
+// Reconnecting after the connection drops mid-game, see `Resync`.
+pub const RESYNC: &str = "resync";
+// Keepalive messages, so a silent peer can be told apart from a dead one.
+pub const HEARTBEAT: &str = "heartbeat";
+// Text messages between the players, see `Chat`.
+pub const CHAT: &str = "chat";
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Extensions {
+    pub extensions: Vec<String>,
+}
+
+// Asks to undo moves until `takeback_to` moves (plies) have been played in the game.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TakebackRequest {
+    pub takeback_to: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct TakebackAnswer {
+    pub takeback_to: u16,
+    pub accepted: bool,
+}
+
+// Sent by both sides first thing after reconnecting: the position and how many
+// moves (plies) have been played, so a move lost in the drop can be made up for.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Resync {
+    pub fen: String,
+    pub ply: u16,
+}
+
+// Counts up with every one sent, nothing reads it but it keeps the message from being empty.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Heartbeat {
+    pub beat: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Chat {
+    pub text: String,
+}
+
+// The first thing a spectator sends, so the host can tell it from the opponent.
+// It is not an extension, spectators use their own connection.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Spectate {
+    pub spectator: String,
+}
+
+// What spectators are sent: the players, where the game started, every move
+// since in UCI and how it ended. Each one replaces the last, so takebacks and
+// resyncs need nothing special.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Broadcast {
+    pub white: Option<String>,
+    pub black: Option<String>,
+    pub start: String,
+    pub moves: Vec<String>,
+    pub end: Option<String>,
+}
+
+// Anything the peer may send once the game is running.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Incoming {
+    Move(chess_networking::Move),
+    Ack(Ack),
+    // Our clients answer the host's `Start` with their own name, other clients never send it.
+    Start(chess_networking::Start),
+    Extensions(Extensions),
+    TakebackRequest(TakebackRequest),
+    TakebackAnswer(TakebackAnswer),
+    Resync(Resync),
+    Heartbeat(Heartbeat),
+    Chat(Chat),
+    Unknown(IgnoredAny),
+}
+
+fn decode_error_to_io(err: rmp_serde::decode::Error) -> io::Error{
+    return match err {
+        rmp_serde::decode::Error::InvalidMarkerRead(err) => err,
+        rmp_serde::decode::Error::InvalidDataRead(err) => err,
+        other => io::Error::new(io::ErrorKind::InvalidData, other),
+    }
+}
+
+pub fn chess_lib_state_to_network_state(board: &chess_lib::Game) -> Option<chess_networking::GameState>{
+    return match board.get_game_state(){
         chess_lib::GameState::Active => None,
-        chess_lib::GameState::GameOver => Some(chess_networking::GameState::CheckMate),
-        chess_lib::GameState::Check => None
+        chess_lib::GameState::Check => None,
+        chess_lib::GameState::GameOver => match board.get_game_over_reason(){
+            Some(chess_lib::GameOverReason::Checkmate) => Some(chess_networking::GameState::CheckMate),
+            _ => Some(chess_networking::GameState::Draw),
+        }
     }
 }
-pub fn do_move(stream: &mut TcpStream, _move: chess_lib::Move, promotion: Option<chess_networking::PromotionPiece>) -> (bool, Option<chess_networking::GameState>){
-    println!("{} {} {} {}", _move.from.file, _move.from.rank, _move.to.file, _move.to.rank);
-    let to_write = chess_networking::Move {
+
+pub fn to_network_promotion(piece: PieceType) -> Option<PromotionPiece>{
+    return match piece {
+        PieceType::Queen => Some(PromotionPiece::Queen),
+        PieceType::Rook => Some(PromotionPiece::Rook),
+        PieceType::Bishop => Some(PromotionPiece::Bishop),
+        PieceType::Knight => Some(PromotionPiece::Knight),
+        _ => None,
+    }
+}
+
+pub fn from_network_promotion(piece: PromotionPiece) -> PieceType{
+    return match piece {
+        PromotionPiece::Queen => PieceType::Queen,
+        PromotionPiece::Rook => PieceType::Rook,
+        PromotionPiece::Bishop => PieceType::Bishop,
+        PromotionPiece::Knight => PieceType::Knight,
+    }
+}
+
+pub fn to_network_move(_move: &chess_lib::Move, offer_draw: bool) -> chess_networking::Move{
+    return chess_networking::Move {
         from: (
             _move.from.file as u8,
             _move.from.rank as u8,
@@ -23,76 +186,552 @@ pub fn do_move(stream: &mut TcpStream, _move: chess_lib::Move, promotion: Option
             _move.to.rank as u8,
         ),
         forfeit: false,
-        offer_draw: false,
-        promotion: promotion,
+        offer_draw: offer_draw,
+        promotion: _move.promotion_choice.and_then(to_network_promotion),
     };
-    stream.write_all(&Vec::try_from(to_write).unwrap());
-    let mut buf = [0u8; 512];
-    loop {
-        stream.read(&mut buf);
-        match chess_networking::Ack::try_from(&buf[..]){
-            Ok(_Ack) => {
-                return (_Ack.ok, _Ack.end_state);
-            },
-            _ => {}
-        }
+}
+
+// Returns None if the squares are off the board or the move makes no sense on `board`.
+pub fn to_chess_lib_move(board: &chess_lib::Game, _move: &chess_networking::Move) -> Option<chess_lib::Move>{
+    if _move.from.0 > 7 || _move.from.1 > 7 || _move.to.0 > 7 || _move.to.1 > 7 {
+        return None;
+    }
+    let from = Position::new(_move.from.1 as usize, _move.from.0 as usize).ok()?;
+    let to = Position::new(_move.to.1 as usize, _move.to.0 as usize).ok()?;
+    let mut mv = chess_lib::Move::new(board, from, to).ok()?;
+    if mv.is_promotion(){
+        mv.promotion_choice = Some(from_network_promotion(_move.promotion.unwrap_or(PromotionPiece::Queen)));
     }
+    return Some(mv);
 }
 
-pub fn await_move(stream: &mut TcpStream) -> (chess_networking::Move){
-    let mut buf = [0u8; 512];
-    loop {
-        stream.read(&mut buf);
-        match chess_networking::Move::try_from(&buf[..]){
-            Ok(_Move) => {
-                return (_Move);
-            },
-            _ => {}
-        }
+// Plays a move received from the peer, returns whether it was legal.
+pub fn apply_network_move(board: &mut chess_lib::Game, _move: &chess_networking::Move) -> bool{
+    return match to_chess_lib_move(board, _move) {
+        Some(mv) => board.make_move(mv).is_ok(),
+        None => false,
     }
 }
 
-pub fn send_ack(stream: &mut TcpStream, valid_move: bool, state: Option<chess_networking::GameState>){
-    println!("valid move: {}", valid_move);
+fn write_message<T>(stream: &mut TcpStream, message: T) -> io::Result<()>
+where Vec<u8>: TryFrom<T> {
+    let bytes = Vec::try_from(message).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "failed to encode message"))?;
+    return stream.write_all(&bytes);
+}
+
+// Our own messages are encoded with field names, so they can't be mistaken for the shared ones.
+pub fn write_extension<T: Serialize, W: Write>(stream: &mut W, message: &T) -> io::Result<()>{
+    let bytes = rmp_serde::to_vec_named(message).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    return stream.write_all(&bytes);
+}
+
+pub fn do_move(stream: &mut TcpStream, _move: chess_lib::Move, offer_draw: bool) -> io::Result<Ack>{
+    write_message(stream, to_network_move(&_move, offer_draw))?;
+    return read_message(stream);
+}
+
+pub fn send_forfeit(stream: &mut TcpStream) -> io::Result<Ack>{
+    let to_write = chess_networking::Move {
+        from: (0, 0),
+        to: (0, 0),
+        forfeit: true,
+        offer_draw: false,
+        promotion: None,
+    };
+    write_message(stream, to_write)?;
+    return read_message(stream);
+}
+
+pub fn await_move(stream: &mut TcpStream) -> io::Result<chess_networking::Move>{
+    return read_message(stream);
+}
+
+pub fn send_ack(stream: &mut TcpStream, valid_move: bool, state: Option<chess_networking::GameState>) -> io::Result<()>{
     let to_write = chess_networking::Ack{
         ok: valid_move,
         end_state: state
     };
-    stream.write_all(&Vec::try_from(to_write).unwrap());
+    return write_message(stream, to_write);
+}
+
+// `bind` is a host name or an IP address, IPv6 with or without brackets: `[::]` listens on every interface.
+pub fn listen(bind: &str, port: &str) -> io::Result<TcpListener>{
+    let port: u16 = port.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("Bad port '{}'", port)))?;
+    return TcpListener::bind((bare_host(bind), port));
 }
 
-pub fn start_server(port: &str, name: &str) -> (TcpStream, Option<String>){
-    let connection = TcpListener::bind(String::from(LOCAL_HOST) + ":" + port);
-    if connection.is_err() {
-        println!("Failed to setup listener on port {}", port);
+fn bare_host(host: &str) -> &str{
+    let host = host.trim();
+    return host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+}
+
+// Splits an address to join as typed into the lobby. The host can be a name, an
+// IPv4 address or an IPv6 address, which needs brackets to tell it from the port.
+pub fn split_host_port(address: &str) -> Result<(String, u16), String>{
+    let address = address.trim();
+    let split = address.rsplit_once(':').filter(|_| !address.ends_with(']'));
+    let Some((host, port)) = split else {
+        return Err(String::from("Add the port, like example.com:8080"));
+    };
+    if host.contains(':') && !(host.starts_with('[') && host.ends_with(']')) {
+        return Err(String::from("Put IPv6 addresses in brackets, like [::1]:8080"));
     }
-    let (mut stream, _addr) = connection.unwrap().accept().unwrap();
-    
+    let host = bare_host(host);
+    if host.is_empty() {
+        return Err(String::from("Enter the host before the port"));
+    }
+    let port = port.parse().map_err(|_| String::from("Enter a port between 0 and 65535"))?;
+    return Ok((String::from(host), port));
+}
+
+// Addresses a player on another machine could join a game hosted on `bind` at. Binding
+// every interface offers each of them, IPv6 ones only with `[::]`, anything else just itself.
+pub fn shareable_addresses(bind: &str, port: u16) -> Vec<String>{
+    let ips = match bare_host(bind).parse::<IpAddr>() {
+        Ok(bound) if bound.is_unspecified() => if_addrs::get_if_addrs().unwrap_or_default().iter()
+            .filter(|interface| !interface.is_loopback())
+            .map(|interface| interface.ip())
+            // Link-local IPv6 addresses only work with the interface named, so they aren't worth sharing.
+            .filter(|ip| match ip {
+                IpAddr::V4(_) => true,
+                IpAddr::V6(v6) => bound.is_ipv6() && v6.segments()[0] & 0xffc0 != 0xfe80,
+            })
+            .collect(),
+        Ok(bound) => vec![bound],
+        Err(_) => return vec![format!("{}:{}", bare_host(bind), port)],
+    };
+    return ips.into_iter().map(|ip| SocketAddr::new(ip, port).to_string()).collect();
+}
+
+fn non_empty(name: &str) -> Option<String>{
+    return match name.trim() {
+        "" => None,
+        name => Some(String::from(name)),
+    };
+}
+
+// `fen` is the position the game starts from, none for the usual one.
+fn send_start(stream: &mut TcpStream, name: &str, fen: Option<String>) -> io::Result<()>{
     let start = chess_networking::Start{
         is_white: false,
-        name: Some(String::from(name)),
+        name: non_empty(name),
+        fen,
+        time: None,
+        inc: None,
+    };
+    return write_message(stream, start);
+}
+
+pub fn start_server(bind: &str, port: &str, name: &str) -> io::Result<(TcpStream, Option<String>)>{
+    let (mut stream, _addr) = listen(bind, port)?.accept()?;
+    send_start(&mut stream, name, None)?;
+    return Ok((stream, None));
+}
+
+// Returns the host's name, our side and the position the host wants to start from.
+// `address` is anything `split_host_port` takes, every address a host name resolves to is tried.
+pub fn start_client(address: &str, name: &str) -> io::Result<(TcpStream, Option<String>, chess_lib::Colour, Option<String>)>{
+    let (host, port) = split_host_port(address).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stream = TcpStream::connect((host.as_str(), port))?;
+    let start: chess_networking::Start = read_message(&mut stream)?;
+    let color = match start.is_white  {
+        true => chess_lib::Colour::White,
+        false => chess_lib::Colour::Black
+    };
+    // Tell the host who we are, `is_white` is the host's side as seen from here.
+    let reply = chess_networking::Start{
+        is_white: !start.is_white,
+        name: non_empty(name),
         fen: None,
         time: None,
         inc: None,
     };
-    stream.write(&Vec::try_from(start).unwrap());
-    return (stream, None);
+    write_message(&mut stream, reply)?;
+    return Ok((stream, start.name, color, start.fen));
+}
+
+pub enum NetworkEvent {
+    // `fen` is the starting position when it isn't the usual one.
+    Connected { player_side: chess_lib::Colour, opponent_name: Option<String>, fen: Option<String> },
+    // The client told the host its name after the handshake.
+    OpponentName(String),
+    Move(chess_networking::Move),
+    Ack(Ack),
+    // The extensions the peer announced, not sent at all by peers without any.
+    Extensions(Vec<String>),
+    TakebackRequested(u16),
+    TakebackAnswered { takeback_to: u16, accepted: bool },
+    Resync { fen: String, ply: u16 },
+    // Nothing heard from a peer that sends heartbeats for a while, or heard from it again.
+    Lagging(bool),
+    Chat(String),
+    // The game being watched, only for spectators.
+    Broadcast(Broadcast),
+    Disconnected(String),
+}
+
+enum Outgoing {
+    Move(chess_networking::Move),
+    Ack(Ack),
+    TakebackRequest(TakebackRequest),
+    TakebackAnswer(TakebackAnswer),
+    Resync(Resync),
+    Heartbeat,
+    Chat(Chat),
 }
 
-pub fn start_client(ip: &str, name: &str) -> (TcpStream, Option<String>, chess_lib::Colour){
-    let mut stream = TcpStream::connect(ip).unwrap();
-    let mut buf = [0u8; 512];
+// How a running game's connection is doing, for the side panel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LinkStatus {
+    Connected,
+    Lagging,
+    Lost,
+}
+
+// A game connection running on its own thread, so the window never blocks on the network.
+// Dropping it cancels a pending handshake or closes the connection.
+pub struct Connection {
+    outgoing: Sender<Outgoing>,
+    events: Receiver<NetworkEvent>,
+    // Only for games we host.
+    broadcasts: Option<Sender<Broadcast>>,
+}
+
+impl Connection {
+    // Hosts a game that starts from `fen`, or the usual position when it is none.
+    // A peer that sends heartbeats is given up on after `timeout` without a word.
+    // Anyone else connecting to the same port is a spectator and gets every `broadcast`.
+    pub fn host(bind: String, port: String, name: String, fen: Option<String>, timeout: Duration) -> Connection{
+        let (joined, joined_rx) = mpsc::channel();
+        let (broadcasts, broadcasts_rx) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+        let acceptor_closed = closed.clone();
+        let mut connection = Connection::spawn(timeout, move |outgoing| {
+            let listener = listen_when_free(&bind, &port)?;
+            // Spectators that turn up before the opponent wait for the game to start.
+            let mut stream = loop {
+                let mut stream = accept_cancellable(&listener, outgoing)?;
+                if !is_spectator(&mut stream) {
+                    break stream;
+                }
+                let _ = joined.send(stream);
+            };
+            send_start(&mut stream, &name, fen.clone())?;
+            thread::spawn(move || accept_spectators(listener, joined, acceptor_closed));
+            return Ok((stream, chess_lib::Colour::White, None, fen));
+        });
+        thread::spawn(move || run_spectators(broadcasts_rx, joined_rx, closed));
+        connection.broadcasts = Some(broadcasts);
+        return connection;
+    }
+
+    pub fn join(address: String, name: String, timeout: Duration) -> Connection{
+        return Connection::spawn(timeout, move |_| {
+            let (stream, opponent_name, player_side, fen) = start_client(&address, &name)?;
+            return Ok((stream, player_side, opponent_name, fen));
+        });
+    }
+
+    // Joins again after the connection dropped, trying until the host is back or this is dropped.
+    pub fn rejoin(address: String, name: String, timeout: Duration) -> Connection{
+        return Connection::spawn(timeout, move |outgoing| {
+            loop {
+                match start_client(&address, &name) {
+                    Ok((stream, opponent_name, player_side, fen)) => return Ok((stream, player_side, opponent_name, fen)),
+                    Err(_) => {
+                        if let Err(TryRecvError::Disconnected) = outgoing.try_recv() {
+                            return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+                        }
+                        thread::sleep(RETRY_INTERVAL);
+                    },
+                }
+            }
+        });
+    }
+
+    fn spawn<F>(timeout: Duration, handshake: F) -> Connection
+    where F: FnOnce(&Receiver<Outgoing>) -> io::Result<(TcpStream, chess_lib::Colour, Option<String>, Option<String>)> + Send + 'static {
+        let (outgoing, outgoing_rx) = mpsc::channel();
+        let (events_tx, events) = mpsc::channel();
+        thread::spawn(move || {
+            let stream = match handshake(&outgoing_rx) {
+                Ok((stream, player_side, opponent_name, fen)) => {
+                    let _ = events_tx.send(NetworkEvent::Connected { player_side, opponent_name, fen });
+                    stream
+                },
+                Err(err) => {
+                    let _ = events_tx.send(NetworkEvent::Disconnected(err.to_string()));
+                    return;
+                }
+            };
+            run_connection(stream, outgoing_rx, events_tx, timeout);
+        });
+        return Connection { outgoing, events, broadcasts: None };
+    }
+
+    // Watches a game hosted at `address` without playing, each update comes as `NetworkEvent::Broadcast`.
+    pub fn watch(address: String, name: String) -> Connection{
+        let (outgoing, outgoing_rx) = mpsc::channel::<Outgoing>();
+        let (events_tx, events) = mpsc::channel();
+        thread::spawn(move || {
+            let connected = split_host_port(&address)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+                .and_then(|(host, port)| TcpStream::connect((host.as_str(), port)))
+                .and_then(|mut stream| {
+                    write_extension(&mut stream, &Spectate { spectator: name })?;
+                    return Ok((stream.try_clone()?, stream));
+                });
+            let (stream, mut reader) = match connected {
+                Ok(streams) => streams,
+                Err(err) => {
+                    let _ = events_tx.send(NetworkEvent::Disconnected(err.to_string()));
+                    return;
+                }
+            };
+            thread::spawn(move || {
+                loop {
+                    let event = match read_message::<Broadcast, _>(&mut reader) {
+                        Ok(broadcast) => NetworkEvent::Broadcast(broadcast),
+                        Err(err) => NetworkEvent::Disconnected(err.to_string()),
+                    };
+                    let disconnected = matches!(event, NetworkEvent::Disconnected(_));
+                    if events_tx.send(event).is_err() || disconnected {
+                        return;
+                    }
+                }
+            });
+            // Spectators never send anything, this only waits for the `Connection` to be dropped.
+            let _ = outgoing_rx.recv();
+            let _ = stream.shutdown(Shutdown::Both);
+        });
+        return Connection { outgoing, events, broadcasts: None };
+    }
+
+    pub fn poll(&self) -> Option<NetworkEvent>{
+        return self.events.try_recv().ok();
+    }
+
+    pub fn send_move(&self, mv: chess_networking::Move){
+        let _ = self.outgoing.send(Outgoing::Move(mv));
+    }
+
+    pub fn send_ack(&self, valid_move: bool, state: Option<chess_networking::GameState>){
+        let _ = self.outgoing.send(Outgoing::Ack(Ack { ok: valid_move, end_state: state }));
+    }
+
+    // Only send these to peers that announced `TAKEBACK`.
+    pub fn request_takeback(&self, takeback_to: u16){
+        let _ = self.outgoing.send(Outgoing::TakebackRequest(TakebackRequest { takeback_to }));
+    }
+
+    pub fn answer_takeback(&self, takeback_to: u16, accepted: bool){
+        let _ = self.outgoing.send(Outgoing::TakebackAnswer(TakebackAnswer { takeback_to, accepted }));
+    }
+
+    // Only to peers that announced `CHAT`.
+    pub fn send_chat(&self, text: String){
+        let _ = self.outgoing.send(Outgoing::Chat(Chat { text }));
+    }
+
+    // Sent on to everyone watching a game we host, and to anyone who starts watching later.
+    pub fn broadcast(&self, broadcast: Broadcast){
+        if let Some(broadcasts) = &self.broadcasts {
+            let _ = broadcasts.send(broadcast);
+        }
+    }
+
+    // Only to peers that announced `RESYNC`, after reconnecting.
+    pub fn resync(&self, fen: String, ply: u16){
+        let _ = self.outgoing.send(Outgoing::Resync(Resync { fen, ply }));
+    }
+}
+
+// Waits for a client, giving up as soon as the owning `Connection` is dropped.
+fn accept_cancellable(listener: &TcpListener, outgoing: &Receiver<Outgoing>) -> io::Result<TcpStream>{
+    listener.set_nonblocking(true)?;
+    loop {
+        match listener.accept() {
+            Ok((stream, _addr)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if let Err(TryRecvError::Disconnected) = outgoing.try_recv() {
+                    return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+                }
+                thread::sleep(Duration::from_millis(50));
+            },
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+// The listener of a game we just stopped hosting may not be closed yet, see `accept_spectators`.
+fn listen_when_free(bind: &str, port: &str) -> io::Result<TcpListener>{
+    let deadline = Instant::now() + RETRY_INTERVAL;
+    loop {
+        match listen(bind, port) {
+            Err(err) if err.kind() == io::ErrorKind::AddrInUse && Instant::now() < deadline => thread::sleep(Duration::from_millis(50)),
+            result => return result,
+        }
+    }
+}
+
+// Spectators say so as soon as they connect, players wait for the host's `Start`.
+fn is_spectator(stream: &mut TcpStream) -> bool{
+    if stream.set_read_timeout(Some(SPECTATE_WAIT)).is_err() {
+        return false;
+    }
+    let spectator = stream.peek(&mut [0]).is_ok_and(|read| read > 0) && read_message::<Spectate, _>(stream).is_ok();
+    let _ = stream.set_read_timeout(None);
+    return spectator;
+}
+
+// Takes spectators on the game's port until `closed`, anything else connecting is turned away.
+fn accept_spectators(listener: TcpListener, joined: Sender<TcpStream>, closed: Arc<AtomicBool>){
+    if listener.set_nonblocking(true).is_err() {
+        return;
+    }
+    while !closed.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((mut stream, _addr)) => {
+                if stream.set_nonblocking(false).is_ok() && is_spectator(&mut stream) && joined.send(stream).is_err() {
+                    return;
+                }
+            },
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(50)),
+            Err(_) => return,
+        }
+    }
+}
+
+// Sends every spectator each broadcast, starting the ones that join later off with the
+// latest. Closes them all and stops `accept_spectators` once the `Connection` is dropped.
+fn run_spectators(broadcasts: Receiver<Broadcast>, joined: Receiver<TcpStream>, closed: Arc<AtomicBool>){
+    let mut spectators: Vec<TcpStream> = Vec::new();
+    let mut latest: Option<Broadcast> = None;
     loop {
-        stream.read(&mut buf);
-        match chess_networking::Start::try_from(&buf[..]){
-            Ok(_Start) => {
-                let color = match _Start.is_white  {
-                    true => chess_lib::Colour::White,
-                    false => chess_lib::Colour::Black
-                };
-                return (stream, _Start.name, color);
+        let next = broadcasts.recv_timeout(Duration::from_millis(100));
+        for mut stream in joined.try_iter() {
+            let _ = stream.set_write_timeout(Some(SPECTATOR_WRITE_TIMEOUT));
+            let caught_up = match &latest {
+                Some(latest) => write_extension(&mut stream, latest).is_ok(),
+                None => true,
+            };
+            if caught_up {
+                spectators.push(stream);
+            }
+        }
+        match next {
+            Ok(broadcast) => {
+                spectators.retain_mut(|stream| write_extension(stream, &broadcast).is_ok());
+                latest = Some(broadcast);
             },
-            _ => {}
+            Err(RecvTimeoutError::Timeout) => {},
+            Err(RecvTimeoutError::Disconnected) => break,
         }
     }
-}
\ No newline at end of file
+    closed.store(true, Ordering::Relaxed);
+    for stream in spectators {
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+// Reads on a second thread and writes on this one until either side hangs up.
+// The writer also sends the heartbeats and watches how long the reader has gone without a message.
+fn run_connection(mut stream: TcpStream, outgoing: Receiver<Outgoing>, events: Sender<NetworkEvent>, timeout: Duration){
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => reader,
+        Err(err) => {
+            let _ = events.send(NetworkEvent::Disconnected(err.to_string()));
+            return;
+        }
+    };
+    let announce = Extensions { extensions: EXTENSIONS.iter().map(|name| name.to_string()).collect() };
+    if let Err(err) = write_extension(&mut stream, &announce) {
+        let _ = events.send(NetworkEvent::Disconnected(err.to_string()));
+        return;
+    }
+    let reader_events = events.clone();
+    let heard = Arc::new(Mutex::new(Instant::now()));
+    let beats = Arc::new(AtomicBool::new(false));
+    let (reader_heard, reader_beats) = (heard.clone(), beats.clone());
+    thread::spawn(move || {
+        loop {
+            let message = read_message::<Incoming, _>(&mut reader);
+            if message.is_ok() {
+                *reader_heard.lock().unwrap() = Instant::now();
+            }
+            let event = match message {
+                Ok(Incoming::Move(mv)) => NetworkEvent::Move(mv),
+                Ok(Incoming::Ack(ack)) => NetworkEvent::Ack(ack),
+                Ok(Incoming::Start(start)) => match start.name {
+                    Some(name) => NetworkEvent::OpponentName(name),
+                    None => continue,
+                },
+                Ok(Incoming::Extensions(announced)) => {
+                    // Peers that don't send heartbeats may stay quiet for as long as they think.
+                    if announced.extensions.iter().any(|name| name == HEARTBEAT) {
+                        reader_beats.store(true, Ordering::Relaxed);
+                        let _ = reader.set_read_timeout(Some(timeout));
+                    }
+                    NetworkEvent::Extensions(announced.extensions)
+                },
+                Ok(Incoming::TakebackRequest(request)) => NetworkEvent::TakebackRequested(request.takeback_to),
+                Ok(Incoming::TakebackAnswer(answer)) => NetworkEvent::TakebackAnswered { takeback_to: answer.takeback_to, accepted: answer.accepted },
+                Ok(Incoming::Resync(resync)) => NetworkEvent::Resync { fen: resync.fen, ply: resync.ply },
+                Ok(Incoming::Chat(chat)) => NetworkEvent::Chat(chat.text),
+                Ok(Incoming::Heartbeat(_)) | Ok(Incoming::Unknown(_)) => continue,
+                Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                    let _ = reader_events.send(NetworkEvent::Disconnected(format!("Nothing heard for {} seconds", timeout.as_secs())));
+                    return;
+                },
+                Err(err) => {
+                    let _ = reader_events.send(NetworkEvent::Disconnected(err.to_string()));
+                    return;
+                }
+            };
+            if reader_events.send(event).is_err() {
+                return;
+            }
+        }
+    });
+    let mut next_beat = Instant::now() + KEEPALIVE_INTERVAL;
+    let mut beat: u32 = 0;
+    let mut lagging = false;
+    loop {
+        let message = match outgoing.recv_timeout(next_beat.saturating_duration_since(Instant::now())) {
+            Ok(message) => message,
+            Err(RecvTimeoutError::Timeout) => {
+                next_beat = Instant::now() + KEEPALIVE_INTERVAL;
+                if !beats.load(Ordering::Relaxed) {
+                    continue;
+                }
+                let lag = heard.lock().unwrap().elapsed() > LAG_AFTER;
+                if lag != lagging {
+                    lagging = lag;
+                    let _ = events.send(NetworkEvent::Lagging(lag));
+                }
+                Outgoing::Heartbeat
+            },
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        let result = match message {
+            Outgoing::Move(mv) => write_message(&mut stream, mv),
+            Outgoing::Ack(ack) => write_message(&mut stream, ack),
+            Outgoing::TakebackRequest(request) => write_extension(&mut stream, &request),
+            Outgoing::TakebackAnswer(answer) => write_extension(&mut stream, &answer),
+            Outgoing::Resync(resync) => write_extension(&mut stream, &resync),
+            Outgoing::Chat(chat) => write_extension(&mut stream, &chat),
+            Outgoing::Heartbeat => {
+                beat = beat.wrapping_add(1);
+                write_extension(&mut stream, &Heartbeat { beat })
+            },
+        };
+        if let Err(err) = result {
+            let _ = events.send(NetworkEvent::Disconnected(err.to_string()));
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
@@ -0,0 +1,270 @@
+use chess_lib::{Game, GameOverReason, GameState, Move, Piece, PieceType, Position};
+
+use crate::pieces::piece_letter;
+
+const PROMOTIONS: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+// What a typed move turned out to be, checked against the legal moves as the player types.
+pub enum ParsedMove {
+    Empty,
+    Legal(Move),
+    // Not a move yet, but typing more could make it one.
+    Incomplete,
+    // Fits more than one legal move, like `Nd2` with both knights able to go there.
+    Ambiguous,
+    Invalid,
+}
+
+// Board index to a square name like `e4`.
+pub fn square_name(index: usize) -> String{
+    return format!("{}{}", (b'a' + (index % 8) as u8) as char, 8 - index / 8);
+}
+
+pub fn parse_square(name: &str) -> Option<usize>{
+    let bytes = name.as_bytes();
+    if bytes.len() != 2 || !(b'a'..=b'h').contains(&bytes[0]) || !(b'1'..=b'8').contains(&bytes[1]) {
+        return None;
+    }
+    return Some((7 - (bytes[1] - b'1') as usize) * 8 + (bytes[0] - b'a') as usize);
+}
+
+// Every legal move for the side to move. Promotions come once per piece the pawn can become.
+pub fn legal_moves(game: &Game) -> Vec<Move>{
+    let active = game.get_active_colour();
+    let mut moves = Vec::new();
+    for (index, piece) in game.get_board().iter().enumerate() {
+        if !piece.is_some_and(|piece| piece.colour == active) {
+            continue;
+        }
+        let Ok(from) = Position::new_from_idx(index) else {
+            continue;
+        };
+        for mv in game.get_legal_moves_from(from).unwrap_or_default() {
+            if !mv.is_promotion() {
+                moves.push(mv);
+                continue;
+            }
+            for piece_type in PROMOTIONS {
+                let mut mv = mv;
+                mv.promotion_choice = Some(piece_type);
+                moves.push(mv);
+            }
+        }
+    }
+    return moves;
+}
+
+pub fn uci(mv: &Move) -> String{
+    let promotion = mv.promotion_choice.map(|piece_type| piece_letter(piece_type).to_ascii_lowercase().to_string());
+    return format!("{}{}{}", square_name(mv.from.idx), square_name(mv.to.idx), promotion.unwrap_or_default());
+}
+
+// Standard algebraic notation for a legal move, without the check or mate suffix.
+pub fn san(game: &Game, mv: &Move) -> String{
+    return san_among(&game.get_board(), &legal_moves(game), mv, true);
+}
+
+// `+` or `#` for the position a move led to, empty otherwise.
+pub fn check_suffix(after: &Game) -> &'static str{
+    return match after.get_game_state() {
+        GameState::Check => "+",
+        GameState::GameOver if matches!(after.get_game_over_reason(), Some(GameOverReason::Checkmate)) => "#",
+        _ => "",
+    };
+}
+
+// Leaving out `disambiguate` gives the short form players type when they
+// forget that two pieces can reach the square.
+fn san_among(board: &[Option<Piece>; 64], legal: &[Move], mv: &Move, disambiguate: bool) -> String{
+    let (from, to) = (mv.from.idx, mv.to.idx);
+    let Some(piece) = board[from] else {
+        return uci(mv);
+    };
+    let letter = piece.to_char();
+    let file_distance = (from % 8).abs_diff(to % 8);
+    if letter == 'K' && file_distance == 2 {
+        return String::from(if to % 8 > from % 8 { "O-O" } else { "O-O-O" });
+    }
+    let capture = board[to].is_some() || (letter == 'P' && file_distance == 1);
+    let mut text = String::new();
+    if letter == 'P' {
+        if capture {
+            text.push(square_name(from).remove(0));
+        }
+    } else {
+        text.push(letter);
+        if disambiguate {
+            text.push_str(&disambiguation(board, legal, mv));
+        }
+    }
+    if capture {
+        text.push('x');
+    }
+    text.push_str(&square_name(to));
+    if let Some(piece_type) = mv.promotion_choice {
+        text.push('=');
+        text.push(piece_letter(piece_type));
+    }
+    return text;
+}
+
+// The file, rank or both of the moving piece, when another piece of the same kind could go to the same square.
+fn disambiguation(board: &[Option<Piece>; 64], legal: &[Move], mv: &Move) -> String{
+    let letter = board[mv.from.idx].map(|piece| piece.to_char());
+    let rivals: Vec<usize> = legal.iter()
+        .filter(|other| other.to.idx == mv.to.idx && other.from.idx != mv.from.idx)
+        .filter(|other| board[other.from.idx].map(|piece| piece.to_char()) == letter)
+        .map(|other| other.from.idx)
+        .collect();
+    let name = square_name(mv.from.idx);
+    if rivals.is_empty() {
+        return String::new();
+    }
+    if rivals.iter().all(|rival| rival % 8 != mv.from.idx % 8) {
+        return name[..1].to_string();
+    }
+    if rivals.iter().all(|rival| rival / 8 != mv.from.idx / 8) {
+        return name[1..].to_string();
+    }
+    return name;
+}
+
+// Characters that can appear in a move written in SAN or UCI, the lowercase piece letters are UCI promotions.
+pub fn is_move_char(character: char) -> bool{
+    return "abcdefgh12345678KQRBNqrnOo0x=-+#".contains(character);
+}
+
+// Reads a move in SAN (`Nf3`, `exd5`, `e8=Q`, `O-O`) or UCI (`g1f3`, `e7e8q`) for the side to move.
+// Check marks and annotations on the end are ignored, and `0-0` or `e8Q` are accepted too.
+pub fn parse_move(game: &Game, text: &str) -> ParsedMove{
+    let text = text.trim().trim_end_matches(['+', '#', '!', '?']).replace('0', "O");
+    if text.is_empty() {
+        return ParsedMove::Empty;
+    }
+    let board = game.get_board();
+    let legal = legal_moves(game);
+    // UCI is read in any case, SAN needs its capitals to tell the b-pawn from a bishop.
+    let lowercase = text.to_ascii_lowercase();
+    let sans: Vec<String> = legal.iter().map(|mv| san_among(&board, &legal, mv, true)).collect();
+    let exact: Vec<&Move> = legal.iter().zip(&sans)
+        .filter(|(mv, san)| **san == text || san.replace('=', "") == text || uci(mv) == lowercase)
+        .map(|(mv, _)| mv)
+        .collect();
+    if exact.len() == 1 {
+        return ParsedMove::Legal(*exact[0]);
+    }
+    let short = legal.iter().filter(|mv| san_among(&board, &legal, mv, false) == text).count();
+    if exact.len() > 1 || short > 1 {
+        return ParsedMove::Ambiguous;
+    }
+    if legal.iter().zip(&sans).any(|(mv, san)| san.starts_with(&text) || uci(mv).starts_with(&lowercase)) {
+        return ParsedMove::Incomplete;
+    }
+    return ParsedMove::Invalid;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::square;
+
+    fn game_after(moves: &[&str]) -> Game {
+        let mut game = Game::new();
+        for text in moves {
+            match parse_move(&game, text) {
+                ParsedMove::Legal(mv) => game.make_move(mv).unwrap(),
+                _ => panic!("{} is not legal here", text),
+            };
+        }
+        game
+    }
+
+    fn legal(game: &Game, text: &str) -> (usize, usize) {
+        match parse_move(game, text) {
+            ParsedMove::Legal(mv) => (mv.from.idx, mv.to.idx),
+            _ => panic!("{} should be legal", text),
+        }
+    }
+
+    #[test]
+    fn squares_round_trip() {
+        for index in 0..64 {
+            assert_eq!(parse_square(&square_name(index)), Some(index));
+        }
+        assert_eq!(square_name(0), "a8");
+        assert_eq!(square_name(63), "h1");
+        assert!(parse_square("i1").is_none());
+        assert!(parse_square("a9").is_none());
+    }
+
+    #[test]
+    fn reads_san_and_uci() {
+        let game = Game::new();
+        assert_eq!(legal(&game, "Nf3"), (square("g1"), square("f3")));
+        assert_eq!(legal(&game, "g1f3"), (square("g1"), square("f3")));
+        assert_eq!(legal(&game, "G1F3"), (square("g1"), square("f3")));
+        assert_eq!(legal(&game, "e4+"), (square("e2"), square("e4")));
+        assert_eq!(legal_moves(&game).len(), 20);
+    }
+
+    #[test]
+    fn validates_while_typing() {
+        let game = Game::new();
+        assert!(matches!(parse_move(&game, ""), ParsedMove::Empty));
+        assert!(matches!(parse_move(&game, "N"), ParsedMove::Incomplete));
+        assert!(matches!(parse_move(&game, "Nf"), ParsedMove::Incomplete));
+        assert!(matches!(parse_move(&game, "g1"), ParsedMove::Incomplete));
+        assert!(matches!(parse_move(&game, "Ke2"), ParsedMove::Invalid));
+        assert!(matches!(parse_move(&game, "e5"), ParsedMove::Invalid));
+        assert!(matches!(parse_move(&game, "hello"), ParsedMove::Invalid));
+    }
+
+    #[test]
+    fn captures_and_castling() {
+        let game = game_after(&["e4", "d5"]);
+        assert_eq!(legal(&game, "exd5"), (square("e4"), square("d5")));
+        assert_eq!(san(&game, &match parse_move(&game, "e4d5") { ParsedMove::Legal(mv) => mv, _ => panic!() }), "exd5");
+        let game = game_after(&["e4", "e5", "Nf3", "Nc6", "Bc4", "Bc5"]);
+        assert_eq!(legal(&game, "O-O"), (square("e1"), square("g1")));
+        assert_eq!(legal(&game, "0-0"), (square("e1"), square("g1")));
+        assert!(matches!(parse_move(&game, "O-O-O"), ParsedMove::Invalid));
+    }
+
+    #[test]
+    fn two_knights_on_one_square_need_a_file() {
+        let game = game_after(&["Nf3", "a6", "d3", "a5"]);
+        assert!(matches!(parse_move(&game, "Nd2"), ParsedMove::Ambiguous));
+        assert_eq!(legal(&game, "Nbd2"), (square("b1"), square("d2")));
+        assert_eq!(legal(&game, "Nfd2"), (square("f3"), square("d2")));
+        assert_eq!(legal(&game, "b1d2"), (square("b1"), square("d2")));
+    }
+
+    #[test]
+    fn promotions_name_the_piece() {
+        let game = game_after(&["h4", "g5", "hxg5", "h6", "gxh6", "Bg7", "hxg7", "Nf6"]);
+        assert!(matches!(parse_move(&game, "gxh8"), ParsedMove::Incomplete));
+        for text in ["gxh8=Q", "gxh8Q", "g7h8q"] {
+            match parse_move(&game, text) {
+                ParsedMove::Legal(mv) => assert!(matches!(mv.promotion_choice, Some(PieceType::Queen))),
+                _ => panic!("{} should promote", text),
+            }
+        }
+        match parse_move(&game, "g8=N") {
+            ParsedMove::Legal(mv) => assert!(matches!(mv.promotion_choice, Some(PieceType::Knight))),
+            _ => panic!("g8=N should promote"),
+        }
+    }
+
+    #[test]
+    fn uci_promotions_can_be_typed() {
+        let game = game_after(&["h4", "g5", "hxg5", "h6", "gxh6", "Bg7", "hxg7", "Nf6"]);
+        for text in ["g7h8q", "g7h8r", "g7h8b", "g7h8n"] {
+            let typed: String = text.chars().filter(|character| is_move_char(*character)).collect();
+            assert_eq!(typed, text);
+            match parse_move(&game, &typed) {
+                ParsedMove::Legal(mv) => assert_eq!(uci(&mv), text),
+                _ => panic!("{} should promote", text),
+            }
+        }
+    }
+}
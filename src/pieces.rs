@@ -0,0 +1,154 @@
+use chess_lib::{Colour, PieceType};
+
+// Order of the columns in a sprite sheet, white pieces on the top row and black below.
+pub const PIECE_TYPES: [PieceType; 6] = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+pub const COLOURS: [Colour; 2] = [Colour::White, Colour::Black];
+
+fn column(piece_type: PieceType) -> usize{
+    return match piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 1,
+        PieceType::Rook => 2,
+        PieceType::Bishop => 3,
+        PieceType::Knight => 4,
+        PieceType::Pawn => 5,
+    };
+}
+
+fn row(colour: Colour) -> usize{
+    return if colour.is_black() { 1 } else { 0 };
+}
+
+pub fn piece_letter(piece_type: PieceType) -> char{
+    return match piece_type {
+        PieceType::King => 'K',
+        PieceType::Queen => 'Q',
+        PieceType::Rook => 'R',
+        PieceType::Bishop => 'B',
+        PieceType::Knight => 'N',
+        PieceType::Pawn => 'P',
+    };
+}
+
+// The inverse of `piece_letter`, for pieces stored as letters like the captured lists.
+pub fn piece_type_from_char(letter: char) -> Option<PieceType>{
+    return PIECE_TYPES.into_iter().find(|piece_type| piece_letter(*piece_type) == letter.to_ascii_uppercase());
+}
+
+// A FEN letter as the colour and type of piece, white in capitals.
+pub fn piece_from_letter(letter: char) -> Option<(Colour, PieceType)>{
+    let colour = if letter.is_ascii_lowercase() { Colour::Black } else { Colour::White };
+    return piece_type_from_char(letter).map(|piece_type| (colour, piece_type));
+}
+
+// Name of the image for one piece in a directory piece set, e.g. `n_white.png`.
+pub fn file_name(colour: Colour, piece_type: PieceType, extension: &str) -> String{
+    let side = if colour.is_black() { "black" } else { "white" };
+    return format!("{}_{}.{}", piece_letter(piece_type).to_ascii_lowercase(), side, extension);
+}
+
+// Where a piece sits in a 6x2 sprite sheet, as x, y, width and height fractions of the sheet.
+pub fn sprite_cell(colour: Colour, piece_type: PieceType) -> [f32; 4]{
+    let (width, height) = (1. / PIECE_TYPES.len() as f32, 1. / COLOURS.len() as f32);
+    return [column(piece_type) as f32 * width, row(colour) as f32 * height, width, height];
+}
+
+// One entry per colour and piece type. Entries that failed to load are left
+// empty so the draw code can fall back instead of the whole set failing.
+pub struct PieceAtlas<T> {
+    entries: Vec<Option<T>>,
+}
+
+impl<T> PieceAtlas<T> {
+    pub fn new() -> PieceAtlas<T>{
+        return PieceAtlas { entries: (0..COLOURS.len() * PIECE_TYPES.len()).map(|_| None).collect() };
+    }
+
+    // Builds an atlas by calling `load` for every colour and piece type.
+    pub fn load_with<F: FnMut(Colour, PieceType) -> Option<T>>(mut load: F) -> PieceAtlas<T>{
+        let mut atlas = PieceAtlas::new();
+        for colour in COLOURS {
+            for piece_type in PIECE_TYPES {
+                if let Some(entry) = load(colour, piece_type) {
+                    atlas.insert(colour, piece_type, entry);
+                }
+            }
+        }
+        return atlas;
+    }
+
+    fn index(colour: Colour, piece_type: PieceType) -> usize{
+        return row(colour) * PIECE_TYPES.len() + column(piece_type);
+    }
+
+    pub fn insert(&mut self, colour: Colour, piece_type: PieceType, entry: T){
+        self.entries[PieceAtlas::<T>::index(colour, piece_type)] = Some(entry);
+    }
+
+    pub fn get(&self, colour: Colour, piece_type: PieceType) -> Option<&T>{
+        return self.entries[PieceAtlas::<T>::index(colour, piece_type)].as_ref();
+    }
+
+    pub fn is_empty(&self) -> bool{
+        return self.entries.iter().all(|entry| entry.is_none());
+    }
+
+    pub fn is_complete(&self) -> bool{
+        return self.entries.iter().all(|entry| entry.is_some());
+    }
+
+    // Fills the entries this atlas is missing from `other`.
+    pub fn fill_from(&mut self, other: PieceAtlas<T>){
+        for (entry, fallback) in self.entries.iter_mut().zip(other.entries) {
+            if entry.is_none() {
+                *entry = fallback;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_piece_has_its_own_entry() {
+        let atlas = PieceAtlas::load_with(|colour, piece_type| Some(file_name(colour, piece_type, "png")));
+        assert!(atlas.is_complete());
+        assert_eq!(atlas.get(Colour::White, PieceType::Knight).unwrap(), "n_white.png");
+        assert_eq!(atlas.get(Colour::Black, PieceType::King).unwrap(), "k_black.png");
+        assert_eq!(atlas.get(Colour::Black, PieceType::Pawn).unwrap(), "p_black.png");
+    }
+
+    #[test]
+    fn missing_entries_are_filled_from_a_fallback() {
+        let mut atlas = PieceAtlas::load_with(|colour, piece_type| match piece_type {
+            PieceType::Queen => None,
+            _ => Some(file_name(colour, piece_type, "svg")),
+        });
+        assert!(!atlas.is_complete());
+        assert!(atlas.get(Colour::White, PieceType::Queen).is_none());
+        atlas.fill_from(PieceAtlas::load_with(|colour, piece_type| Some(file_name(colour, piece_type, "png"))));
+        assert!(atlas.is_complete());
+        assert_eq!(atlas.get(Colour::White, PieceType::Queen).unwrap(), "q_white.png");
+        assert_eq!(atlas.get(Colour::White, PieceType::Rook).unwrap(), "r_white.png");
+        assert!(PieceAtlas::<String>::new().is_empty());
+    }
+
+    #[test]
+    fn sprite_cells_follow_the_sheet_layout() {
+        assert_eq!(sprite_cell(Colour::White, PieceType::King), [0., 0., 1. / 6., 0.5]);
+        assert_eq!(sprite_cell(Colour::Black, PieceType::Pawn), [5. / 6., 0.5, 1. / 6., 0.5]);
+    }
+
+    #[test]
+    fn letters_round_trip() {
+        for piece_type in PIECE_TYPES {
+            assert_eq!(piece_type_from_char(piece_letter(piece_type)).map(piece_letter), Some(piece_letter(piece_type)));
+        }
+        assert_eq!(piece_type_from_char('n').map(piece_letter), Some('N'));
+        assert!(piece_type_from_char('x').is_none());
+        assert!(matches!(piece_from_letter('n'), Some((Colour::Black, PieceType::Knight))));
+        assert!(matches!(piece_from_letter('Q'), Some((Colour::White, PieceType::Queen))));
+    }
+}
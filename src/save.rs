@@ -0,0 +1,116 @@
+use chess_lib::{Game, Move};
+use serde::{Deserialize, Serialize};
+
+use crate::chat::ChatLine;
+use crate::fen::{Setup, INITIAL};
+use crate::notation::{parse_move, uci, ParsedMove};
+
+// Where a game in progress is saved when the window closes, and read back by `resume`.
+pub const DEFAULT_PATH: &str = "saved_game.toml";
+
+// A network game in progress, enough to rebuild the board and connect to the same opponent again.
+// Whose turn it is follows from the moves. Games are played without clocks, so there is no time to save.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct SavedGame {
+    // FEN of the position the game started from.
+    pub start: String,
+    // Every move played since, in UCI.
+    pub moves: Vec<String>,
+    pub playing_white: bool,
+    pub name: String,
+    pub opponent_name: Option<String>,
+    pub host: bool,
+    // The port we hosted on, or the address we joined.
+    pub address: String,
+    // The local address we hosted on, missing from games saved before it could be chosen.
+    #[serde(default)]
+    pub bind: Option<String>,
+    // The chat so far. Last, since TOML writes the lines as tables after the other values.
+    #[serde(default)]
+    pub chat: Vec<ChatLine>,
+}
+
+impl SavedGame {
+    pub fn new(start: String, moves: &[Move]) -> SavedGame{
+        return SavedGame {
+            start: start,
+            moves: moves.iter().map(uci).collect(),
+            playing_white: true,
+            name: String::new(),
+            opponent_name: None,
+            host: true,
+            address: String::new(),
+            bind: None,
+            chat: Vec::new(),
+        };
+    }
+
+    pub fn to_toml(&self) -> Result<String, String>{
+        return toml::to_string(self).map_err(|err| err.to_string());
+    }
+
+    pub fn from_toml(text: &str) -> Result<SavedGame, String>{
+        return toml::from_str(text).map_err(|err| err.to_string());
+    }
+
+    // The starting position and the moves played from it, checked to still be legal.
+    pub fn replay(&self) -> Result<(Game, Vec<Move>), String>{
+        return replay(&self.start, &self.moves);
+    }
+}
+
+// Rebuilds a game written down as its starting FEN and UCI moves, like a save or a spectator broadcast.
+pub fn replay(start: &str, moves: &[String]) -> Result<(Game, Vec<Move>), String>{
+    let start = if start == INITIAL { Game::new() } else { Setup::parse(start)?.to_game()? };
+    let mut game = start.clone();
+    let mut played = Vec::new();
+    for (ply, text) in moves.iter().enumerate() {
+        let ParsedMove::Legal(mv) = parse_move(&game, text) else {
+            return Err(format!("Move {} ({}) is not legal", ply + 1, text));
+        };
+        if game.make_move(mv).is_err() {
+            return Err(format!("Move {} ({}) is not legal", ply + 1, text));
+        }
+        played.push(mv);
+    }
+    return Ok((start, played));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(moves: &[&str]) -> SavedGame {
+        let mut saved = SavedGame::new(String::from(INITIAL), &[]);
+        saved.moves = moves.iter().map(|text| text.to_string()).collect();
+        saved.name = String::from("host");
+        saved.address = String::from("8080");
+        saved
+    }
+
+    #[test]
+    fn round_trips_through_toml() {
+        let saved = saved(&["e2e4", "e7e5", "g1f3"]);
+        let text = saved.to_toml().unwrap();
+        assert_eq!(SavedGame::from_toml(&text).unwrap(), saved);
+        let mut named = saved.clone();
+        named.opponent_name = Some(String::from("guest"));
+        named.chat = vec![ChatLine { from_us: false, text: String::from("good luck") }, ChatLine { from_us: true, text: String::from("you too") }];
+        assert_eq!(SavedGame::from_toml(&named.to_toml().unwrap()).unwrap(), named);
+    }
+
+    #[test]
+    fn replays_the_moves() {
+        let (start, moves) = saved(&["e2e4", "e7e5", "e1e2"]).replay().unwrap();
+        assert_eq!(Setup::from_game(&start).to_fen(), INITIAL);
+        assert_eq!(moves.iter().map(uci).collect::<Vec<_>>(), vec!["e2e4", "e7e5", "e1e2"]);
+        let saved_again = SavedGame::new(String::from(INITIAL), &moves);
+        assert_eq!(saved_again.moves, vec!["e2e4", "e7e5", "e1e2"]);
+    }
+
+    #[test]
+    fn rejects_illegal_moves_and_bad_files() {
+        assert_eq!(saved(&["e2e4", "e2e4"]).replay().err(), Some(String::from("Move 2 (e2e4) is not legal")));
+        assert!(SavedGame::from_toml("moves = 3").is_err());
+    }
+}
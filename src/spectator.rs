@@ -0,0 +1,79 @@
+use chess_lib::Game;
+
+use crate::network::Broadcast;
+use crate::save::replay;
+
+// The game as the latest broadcast has it, for a spectator to look at.
+pub struct SpectatorView {
+    pub white: Option<String>,
+    pub black: Option<String>,
+    // How the game ended, in the host's words.
+    pub end: Option<String>,
+    start: Game,
+    game: Game,
+    last_move: Option<(usize, usize)>,
+}
+
+impl SpectatorView {
+    pub fn from_broadcast(broadcast: &Broadcast) -> Result<SpectatorView, String>{
+        let (start, moves) = replay(&broadcast.start, &broadcast.moves)?;
+        let mut game = start.clone();
+        for mv in &moves {
+            let _ = game.make_move(*mv);
+        }
+        return Ok(SpectatorView {
+            white: broadcast.white.clone(),
+            black: broadcast.black.clone(),
+            end: broadcast.end.clone(),
+            start,
+            game,
+            last_move: moves.last().map(|mv| (mv.from.idx, mv.to.idx)),
+        });
+    }
+
+    pub fn start(&self) -> &Game{
+        return &self.start;
+    }
+
+    pub fn game(&self) -> &Game{
+        return &self.game;
+    }
+
+    pub fn last_move(&self) -> Option<(usize, usize)>{
+        return self.last_move;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fen::INITIAL;
+    use crate::test_support::square;
+
+    fn broadcast(moves: &[&str]) -> Broadcast {
+        Broadcast {
+            white: Some(String::from("host")),
+            black: None,
+            start: String::from(INITIAL),
+            moves: moves.iter().map(|text| text.to_string()).collect(),
+            end: None,
+        }
+    }
+
+    #[test]
+    fn shows_the_position_after_the_moves() {
+        let view = SpectatorView::from_broadcast(&broadcast(&["e2e4", "e7e5"])).unwrap();
+        assert!(view.game().get_active_colour() == chess_lib::Colour::White);
+        assert_eq!(view.last_move(), Some((square("e7"), square("e5"))));
+        assert_eq!(view.white.as_deref(), Some("host"));
+        assert!(SpectatorView::from_broadcast(&broadcast(&[])).unwrap().last_move().is_none());
+    }
+
+    #[test]
+    fn rejects_broadcasts_that_dont_replay() {
+        assert!(SpectatorView::from_broadcast(&broadcast(&["e2e4", "e2e4"])).is_err());
+        let mut bad_start = broadcast(&[]);
+        bad_start.start = String::from("not a fen");
+        assert!(SpectatorView::from_broadcast(&bad_start).is_err());
+    }
+}
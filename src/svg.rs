@@ -0,0 +1,52 @@
+use resvg::{tiny_skia, usvg};
+
+// Renders an SVG stretched to `width` by `height` pixels, as RGBA bytes with
+// straight (not premultiplied) alpha, ready to upload as an image.
+pub fn rasterize(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, String>{
+    let tree = usvg::Tree::from_data(data, &usvg::Options::default()).map_err(|err| err.to_string())?;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or_else(|| format!("cannot rasterize at {}x{}", width, height))?;
+    let size = tree.size();
+    let transform = tiny_skia::Transform::from_scale(width as f32 / size.width(), height as f32 / size.height());
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    return Ok(pixmap.pixels().iter().flat_map(|pixel| {
+        let color = pixel.demultiply();
+        [color.red(), color.green(), color.blue(), color.alpha()]
+    }).collect());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HALF_RED: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 10 10" width="10" height="10">
+        <rect x="0" y="0" width="5" height="10" fill="#ff0000"/>
+    </svg>"#;
+
+    fn pixel(pixels: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let start = ((y * width + x) * 4) as usize;
+        pixels[start..start + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn scales_to_the_requested_size() {
+        for size in [10, 64, 133] {
+            let pixels = rasterize(HALF_RED, size, size).unwrap();
+            assert_eq!(pixels.len(), (size * size * 4) as usize);
+            assert_eq!(pixel(&pixels, size, 0, 0), [255, 0, 0, 255]);
+            assert_eq!(pixel(&pixels, size, size - 1, size - 1)[3], 0);
+        }
+    }
+
+    #[test]
+    fn stretches_to_sheet_shapes() {
+        let pixels = rasterize(HALF_RED, 60, 20).unwrap();
+        assert_eq!(pixel(&pixels, 60, 25, 19), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 60, 35, 0)[3], 0);
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert!(rasterize(b"not an svg", 10, 10).is_err());
+        assert!(rasterize(HALF_RED, 0, 10).is_err());
+    }
+}
@@ -0,0 +1,21 @@
+// Helpers shared by the unit tests.
+use chess_lib::{Game, Move, Piece, PieceType, Position};
+
+use crate::notation::parse_square;
+
+pub fn square(name: &str) -> usize {
+    parse_square(name).unwrap()
+}
+
+// The board after playing `moves` from the starting position, promoting to queens.
+pub fn board_after(moves: &[(&str, &str)]) -> [Option<Piece>; 64] {
+    let mut game = Game::new();
+    for (from, to) in moves {
+        let mut mv = Move::new(&game, Position::new_from_idx(square(from)).unwrap(), Position::new_from_idx(square(to)).unwrap()).unwrap();
+        if mv.is_promotion() {
+            mv.promotion_choice = Some(PieceType::Queen);
+        }
+        game.make_move(mv).unwrap();
+    }
+    game.get_board()
+}
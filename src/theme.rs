@@ -0,0 +1,144 @@
+use serde::Deserialize;
+
+// An RGB or RGBA colour written as a list of 3 or 4 bytes in the theme file.
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(try_from = "Vec<u8>")]
+pub struct ThemeColor(pub [u8; 4]);
+
+impl TryFrom<Vec<u8>> for ThemeColor {
+    type Error = String;
+
+    fn try_from(bytes: Vec<u8>) -> Result<ThemeColor, String>{
+        return match bytes[..] {
+            [r, g, b] => Ok(ThemeColor([r, g, b, 255])),
+            [r, g, b, a] => Ok(ThemeColor([r, g, b, a])),
+            _ => Err(format!("expected 3 or 4 colour components, got {}", bytes.len())),
+        };
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub light_square: ThemeColor,
+    pub dark_square: ThemeColor,
+    pub last_move: ThemeColor,
+    pub selected: ThemeColor,
+    pub premove: ThemeColor,
+    pub check: ThemeColor,
+    pub move_hint: ThemeColor,
+    pub capture_hint: ThemeColor,
+    pub en_passant_hint: ThemeColor,
+    pub castle_hint: ThemeColor,
+    // Buttons that are switched on, the name plate of the side to move and the like.
+    pub accent: ThemeColor,
+    // Resource path of the font used for the a-h and 1-8 labels.
+    pub coordinate_font: String,
+    // Resource directory holding `r_black.png`, `k_white.png` and so on.
+    pub pieces: String,
+    // Resource path of a 6x2 sprite sheet used instead of `pieces` when set.
+    #[serde(default)]
+    pub piece_sheet: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ThemeFile {
+    #[serde(rename = "theme")]
+    themes: Vec<Theme>,
+}
+
+impl Default for Theme {
+    // The colours the board always had, used when no theme file can be read.
+    fn default() -> Theme{
+        return Theme {
+            name: String::from("Green"),
+            light_square: ThemeColor([255, 255, 255, 255]),
+            dark_square: ThemeColor([118, 150, 86, 255]),
+            last_move: ThemeColor([255, 230, 51, 115]),
+            selected: ThemeColor([51, 128, 255, 115]),
+            premove: ThemeColor([170, 80, 200, 115]),
+            check: ThemeColor([255, 0, 0, 77]),
+            move_hint: ThemeColor([255, 0, 0, 255]),
+            capture_hint: ThemeColor([255, 0, 0, 255]),
+            en_passant_hint: ThemeColor([255, 140, 0, 255]),
+            castle_hint: ThemeColor([40, 90, 220, 255]),
+            accent: ThemeColor([118, 150, 86, 255]),
+            coordinate_font: String::from("/LiberationMono-Regular.ttf"),
+            pieces: String::from("/svg"),
+            piece_sheet: None,
+        };
+    }
+}
+
+impl Theme {
+    // Resource path of one piece image in this theme's set.
+    pub fn piece_path(&self, file_name: &str) -> String{
+        return format!("{}/{}", self.pieces.trim_end_matches('/'), file_name);
+    }
+}
+
+pub fn parse_themes(text: &str) -> Result<Vec<Theme>, String>{
+    let file: ThemeFile = toml::from_str(text).map_err(|err| err.to_string())?;
+    if file.themes.is_empty() {
+        return Err(String::from("theme file has no [[theme]] entries"));
+    }
+    return Ok(file.themes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pieces::{file_name, COLOURS, PIECE_TYPES};
+    use crate::svg::rasterize;
+
+    #[test]
+    fn parses_themes_with_rgb_and_rgba() {
+        let themes = parse_themes(r#"
+            [[theme]]
+            name = "Test"
+            light_square = [1, 2, 3]
+            dark_square = [4, 5, 6, 7]
+            last_move = [0, 0, 0, 0]
+            selected = [0, 0, 0, 0]
+            premove = [0, 0, 0, 0]
+            check = [0, 0, 0, 0]
+            move_hint = [0, 0, 0]
+            capture_hint = [0, 0, 0]
+            en_passant_hint = [0, 0, 0]
+            castle_hint = [0, 0, 0]
+            accent = [0, 0, 0]
+            coordinate_font = "/font.ttf"
+            pieces = "/pieces/test/"
+        "#).unwrap();
+        assert_eq!(themes.len(), 1);
+        assert_eq!(themes[0].light_square, ThemeColor([1, 2, 3, 255]));
+        assert_eq!(themes[0].dark_square, ThemeColor([4, 5, 6, 7]));
+        assert_eq!(themes[0].piece_path("k_white.png"), "/pieces/test/k_white.png");
+        assert_eq!(themes[0].piece_sheet, None);
+    }
+
+    #[test]
+    fn rejects_bad_colours_and_empty_files() {
+        assert!(parse_themes("").is_err());
+        assert!(parse_themes("[[theme]]\nname = \"Bad\"\nlight_square = [1, 2]\n").is_err());
+    }
+
+    #[test]
+    fn bundled_themes_parse() {
+        let themes = parse_themes(include_str!("../resources/themes.toml")).unwrap();
+        assert_eq!(themes[0], Theme::default());
+        assert_eq!(themes[0].piece_path("k_white.svg"), "/svg/k_white.svg");
+    }
+
+    #[test]
+    fn default_theme_has_svg_pieces() {
+        let theme = Theme::default();
+        for colour in COLOURS {
+            for piece_type in PIECE_TYPES {
+                let path = theme.piece_path(&file_name(colour, piece_type, "svg"));
+                let data = std::fs::read(format!("{}/resources{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap();
+                assert!(rasterize(&data, 64, 64).is_ok(), "{} does not render", path);
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+// Everything is laid out in a fixed 900x720 space, which is scaled to fit the
+// window and centred, leaving bars where the window has a different shape.
+pub const WIDTH: f32 = 900.;
+pub const HEIGHT: f32 = 720.;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Viewport {
+    // Window size in pixels.
+    pub width: f32,
+    pub height: f32,
+    // Window pixels per layout unit.
+    pub scale: f32,
+}
+
+impl Viewport {
+    pub fn fit(width: f32, height: f32) -> Viewport{
+        let scale = (width / WIDTH).min(height / HEIGHT).max(f32::EPSILON);
+        return Viewport { width, height, scale };
+    }
+
+    fn offset(&self) -> (f32, f32){
+        return ((self.width - WIDTH * self.scale) / 2., (self.height - HEIGHT * self.scale) / 2.);
+    }
+
+    // Converts a window pixel, like a mouse position, to layout coordinates.
+    pub fn to_layout(&self, x: f32, y: f32) -> (f32, f32){
+        let (offset_x, offset_y) = self.offset();
+        return ((x - offset_x) / self.scale, (y - offset_y) / self.scale);
+    }
+
+    // The part of the layout the whole window shows, as x, y, width and height.
+    pub fn screen_coordinates(&self) -> [f32; 4]{
+        let (offset_x, offset_y) = self.offset();
+        return [-offset_x / self.scale, -offset_y / self.scale, self.width / self.scale, self.height / self.scale];
+    }
+
+    // How many window pixels `size` layout units cover, at least one.
+    pub fn pixels(&self, size: f32) -> u32{
+        return (size * self.scale).round().max(1.) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_window_is_unscaled() {
+        let viewport = Viewport::fit(WIDTH, HEIGHT);
+        assert_eq!(viewport.scale, 1.);
+        assert_eq!(viewport.to_layout(450., 300.), (450., 300.));
+        assert_eq!(viewport.screen_coordinates(), [0., 0., WIDTH, HEIGHT]);
+        assert_eq!(viewport.pixels(60.), 60);
+    }
+
+    #[test]
+    fn wide_windows_get_bars_on_the_sides() {
+        let viewport = Viewport::fit(2000., 1440.);
+        assert_eq!(viewport.scale, 2.);
+        assert_eq!(viewport.to_layout(100., 0.), (0., 0.));
+        assert_eq!(viewport.to_layout(1900., 1440.), (WIDTH, HEIGHT));
+        assert_eq!(viewport.screen_coordinates(), [-50., 0., 1000., HEIGHT]);
+        assert_eq!(viewport.pixels(60.), 120);
+    }
+
+    #[test]
+    fn small_windows_shrink_the_pieces() {
+        let viewport = Viewport::fit(450., 720.);
+        assert_eq!(viewport.scale, 0.5);
+        assert_eq!(viewport.to_layout(0., 180.), (0., 0.));
+        assert_eq!(viewport.pixels(60.), 30);
+    }
+}
@@ -0,0 +1,614 @@
+use std::io::{self, Read};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use caspervk_chess_gui::controller::{Command, EndReason, GameController, Input, UiState};
+use caspervk_chess_gui::network::{apply_network_move, chess_lib_state_to_network_state, read_message, shareable_addresses, split_host_port, start_client, start_server, to_network_move, write_extension, Broadcast, Chat, Connection, Extensions, Heartbeat, NetworkEvent, Resync, TakebackAnswer, TakebackRequest, CHAT, DEFAULT_TIMEOUT, HEARTBEAT, RESYNC, TAKEBACK};
+use caspervk_chess_gui::fen::{Setup, INITIAL};
+use caspervk_chess_gui::notation::parse_square;
+use chess_lib::{Colour, Game, Move, PieceType, Position};
+use chess_networking::{Ack, GameState, Start};
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+// Reader that keeps a copy of every byte it hands out, so the mock peer can
+// assert on exactly what went over the wire.
+struct Recorder<'a> {
+    stream: &'a mut TcpStream,
+    bytes: Vec<u8>,
+}
+
+impl Read for Recorder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        self.bytes.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+// One message of type `T`, or of any other type so it can be skipped on its own.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneMessage<T> {
+    Wanted(T),
+    Other(IgnoredAny),
+}
+
+// Scripted opponent speaking the raw protocol, with its own copy of the game.
+struct MockPeer {
+    stream: TcpStream,
+    game: Game,
+    // The move we sent and have no ack for yet.
+    pending: Option<Move>,
+}
+
+impl MockPeer {
+    fn new(stream: TcpStream) -> MockPeer {
+        MockPeer { stream, game: Game::new(), pending: None }
+    }
+
+    fn send<T>(&mut self, message: T) where Vec<u8>: TryFrom<T> {
+        use std::io::Write;
+        let bytes = Vec::try_from(message).ok().expect("encode");
+        self.stream.write_all(&bytes).unwrap();
+    }
+
+    // Reads one message and returns it along with the raw bytes it was decoded from,
+    // skipping messages of other types such as the extensions the host announces.
+    fn recv<T: serde::de::DeserializeOwned>(&mut self) -> (T, Vec<u8>) {
+        loop {
+            let mut recorder = Recorder { stream: &mut self.stream, bytes: Vec::new() };
+            if let OneMessage::Wanted(message) = read_message(&mut recorder).unwrap() {
+                return (message, recorder.bytes);
+            }
+        }
+    }
+
+    // Expects `uci` from the peer, checks the bytes and acks it like a well-behaved client.
+    fn expect_move(&mut self, uci: &str) {
+        let expected = uci_move(&self.game, uci);
+        let (received, bytes): (chess_networking::Move, _) = self.recv();
+        assert_eq!(bytes, encode(to_network_move(&expected, false)), "unexpected bytes for {uci}");
+        assert!(!received.forfeit && !received.offer_draw);
+        assert!(apply_network_move(&mut self.game, &received), "mock rejected {uci}");
+        let end_state = chess_lib_state_to_network_state(&self.game);
+        self.send(Ack { ok: true, end_state });
+    }
+
+    // Sends `uci`, it is played once `acked` sees the peer accept it.
+    fn send_move(&mut self, uci: &str, offer_draw: bool) {
+        let mv = uci_move(&self.game, uci);
+        self.send(to_network_move(&mv, offer_draw));
+        self.pending = Some(mv);
+    }
+
+    fn acked(&mut self) -> Ack {
+        let (ack, _): (Ack, _) = self.recv();
+        let mv = self.pending.take().expect("no move waiting for an ack");
+        if ack.ok {
+            self.game.make_move(mv).unwrap();
+        }
+        ack
+    }
+}
+
+// One side of a game played the way the app plays it, a `Connection` driven by a `GameController`.
+struct Player {
+    connection: Connection,
+    controller: GameController,
+    // The move we sent and have no ack for yet.
+    pending: Option<Move>,
+}
+
+impl Player {
+    // Waits for the handshake, None if the connection failed instead.
+    fn handshake(connection: Connection) -> Option<Player> {
+        let events = poll_until(&connection, |event| matches!(event, NetworkEvent::Connected { .. } | NetworkEvent::Disconnected(_)));
+        let Some(NetworkEvent::Connected { player_side, .. }) = events.last() else {
+            return None;
+        };
+        let mut controller = GameController::new(Game::new(), Setup::initial());
+        controller.connected(*player_side);
+        Some(Player { connection, controller, pending: None })
+    }
+
+    // Hands the controller's commands to the connection like `MainState::run_command`.
+    fn run(&mut self, command: Option<Command>) {
+        match command {
+            Some(Command::SendMove(mv)) => {
+                self.connection.send_move(to_network_move(&mv, false));
+                self.pending = Some(mv);
+            },
+            Some(Command::OfferDraw(mv)) => {
+                self.connection.send_move(to_network_move(&mv, true));
+                self.pending = Some(mv);
+            },
+            Some(Command::AnswerDrawOffer(ack)) => self.connection.send_ack(ack.ok, ack.end_state),
+            Some(Command::RequestTakeback(takeback_to)) => self.connection.request_takeback(takeback_to),
+            Some(Command::AnswerTakeback { takeback_to, accepted }) => self.connection.answer_takeback(takeback_to, accepted),
+            None => {},
+        }
+    }
+
+    // Types in `uci` and sends it.
+    fn play(&mut self, uci: &str) {
+        let command = self.controller.handle_input(Input::MoveTyped(uci_move(self.controller.board(), uci)));
+        assert!(matches!(command, Some(Command::SendMove(_) | Command::OfferDraw(_))), "{uci} was not sent");
+        self.run(command);
+    }
+
+    // Waits for the ack to our move and plays it if it was accepted.
+    fn acked(&mut self) -> Ack {
+        let Some(NetworkEvent::Ack(ack)) = poll_until(&self.connection, |event| matches!(event, NetworkEvent::Ack(_))).pop() else {
+            unreachable!();
+        };
+        let mv = self.pending.take().expect("no move waiting for an ack");
+        self.controller.move_acked(mv, &ack);
+        ack
+    }
+
+    // Waits for the opponent's move and acks it, unless a draw offer came with it.
+    fn answer(&mut self) {
+        let Some(NetworkEvent::Move(mv)) = poll_until(&self.connection, |event| matches!(event, NetworkEvent::Move(_))).pop() else {
+            unreachable!();
+        };
+        if let Some(ack) = self.controller.ack_opponent_move(&mv) {
+            self.connection.send_ack(ack.ok, ack.end_state);
+        }
+    }
+}
+
+fn encode<T>(message: T) -> Vec<u8> where Vec<u8>: TryFrom<T> {
+    Vec::try_from(message).ok().expect("encode")
+}
+
+fn position(name: &str) -> Position {
+    Position::new_from_idx(parse_square(name).unwrap()).unwrap()
+}
+
+fn uci_move(game: &Game, uci: &str) -> Move {
+    let mut mv = Move::new(game, position(&uci[0..2]), position(&uci[2..4])).unwrap();
+    mv.promotion_choice = match uci.chars().nth(4) {
+        Some('q') => Some(PieceType::Queen),
+        Some('r') => Some(PieceType::Rook),
+        Some('b') => Some(PieceType::Bishop),
+        Some('n') => Some(PieceType::Knight),
+        _ => None,
+    };
+    mv
+}
+
+fn piece_at(game: &Game, name: &str) -> Option<(char, Colour)> {
+    game.get_board()[parse_square(name).unwrap()].map(|piece| (piece.to_char(), piece.colour))
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+fn connect_with_retry(address: &str) -> TcpStream {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => return stream,
+            Err(err) if Instant::now() > deadline => panic!("could not connect to {address}: {err}"),
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+}
+
+// Hosts a game and joins it with a mock client, checking the `Start` handshake on the way.
+fn host_with_mock_client() -> (Player, MockPeer) {
+    let port = free_port();
+    let host = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, DEFAULT_TIMEOUT);
+    let mut mock = MockPeer::new(connect_with_retry(&format!("127.0.0.1:{port}")));
+    let (start, bytes): (Start, _) = mock.recv();
+    assert!(!start.is_white, "the host always takes white");
+    assert_eq!(start.name.as_deref(), Some("host"));
+    assert!(start.fen.is_none());
+    assert_eq!(bytes, encode(Start { is_white: false, name: Some("host".into()), fen: None, time: None, inc: None }));
+    (Player::handshake(host).expect("host failed"), mock)
+}
+
+// Hosts a game and joins it, both ends playing through `Connection`.
+fn new_game() -> (Player, Player) {
+    let port = free_port();
+    let host = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, DEFAULT_TIMEOUT);
+    // Joining fails straight away until the host is listening.
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let guest = loop {
+        if let Some(guest) = Player::handshake(Connection::join(format!("127.0.0.1:{port}"), "guest".into(), DEFAULT_TIMEOUT)) {
+            break guest;
+        }
+        assert!(Instant::now() < deadline, "could not join");
+        thread::sleep(Duration::from_millis(10));
+    };
+    (Player::handshake(host).expect("host failed"), guest)
+}
+
+// Plays `moves` in turn, whoever's move it is sends it and the other acks it.
+// Every move must be accepted, returns the acks.
+fn play_moves(a: &mut Player, b: &mut Player, moves: &[&str]) -> Vec<Ack> {
+    let mut acks = Vec::new();
+    for uci in moves {
+        let (mover, other) = if a.controller.is_players_turn() { (&mut *a, &mut *b) } else { (&mut *b, &mut *a) };
+        mover.play(uci);
+        other.answer();
+        let ack = mover.acked();
+        assert!(ack.ok, "{uci} was rejected");
+        acks.push(ack);
+    }
+    acks
+}
+
+// Runs `moves` between a host and a guest and returns both games.
+fn run_script(moves: &[&str]) -> (Game, Game) {
+    let (mut host, mut guest) = new_game();
+    play_moves(&mut host, &mut guest, moves);
+    (host.controller.board().clone(), guest.controller.board().clone())
+}
+
+// Collects events from `connection` until one matches `done`, failing after five seconds.
+fn poll_until(connection: &Connection, done: impl Fn(&NetworkEvent) -> bool) -> Vec<NetworkEvent> {
+    let mut events = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !events.last().is_some_and(&done) {
+        assert!(Instant::now() < deadline, "timed out waiting for an event");
+        match connection.poll() {
+            Some(event) => events.push(event),
+            None => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    events
+}
+
+fn assert_same_board(a: &Game, b: &Game) {
+    for idx in 0..64 {
+        let piece_a = a.get_board()[idx].map(|piece| (piece.to_char(), piece.colour));
+        let piece_b = b.get_board()[idx].map(|piece| (piece.to_char(), piece.colour));
+        assert!(piece_a == piece_b, "boards differ at index {idx}");
+    }
+    assert!(a.get_active_colour() == b.get_active_colour());
+}
+
+#[test]
+fn client_handshake_reads_colour_name_and_position() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    let client = thread::spawn(move || start_client(&address, "guest"));
+    let (stream, _) = listener.accept().unwrap();
+    let mut mock = MockPeer::new(stream);
+    let fen = "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1";
+    mock.send(Start { is_white: true, name: Some("mock".into()), fen: Some(fen.into()), time: None, inc: None });
+    let (_stream, name, colour, start_fen) = client.join().unwrap().unwrap();
+    assert_eq!(name.as_deref(), Some("mock"));
+    assert!(colour == Colour::White);
+    assert_eq!(start_fen.as_deref(), Some(fen));
+    let (reply, _): (Start, _) = mock.recv();
+    assert_eq!(reply.name.as_deref(), Some("guest"));
+    assert!(!reply.is_white, "the reply carries the host's side");
+}
+
+#[test]
+fn host_learns_the_client_name() {
+    let port = free_port();
+    let connection = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, DEFAULT_TIMEOUT);
+    let client = thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match start_client(&format!("127.0.0.1:{port}"), "guest") {
+                Ok(connected) => return connected,
+                Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+                Err(err) => panic!("could not join: {err}"),
+            }
+        }
+    });
+    let (_stream, name, _, fen) = client.join().unwrap();
+    assert_eq!(name.as_deref(), Some("host"));
+    assert!(fen.is_none());
+    let mut events = Vec::new();
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline && !events.iter().any(|event| matches!(event, NetworkEvent::OpponentName(_))) {
+        match connection.poll() {
+            Some(event) => events.push(event),
+            None => thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    assert!(matches!(events[0], NetworkEvent::Connected { player_side: Colour::White, opponent_name: None, fen: None }));
+    assert!(matches!(&events[1], NetworkEvent::OpponentName(name) if name == "guest"));
+}
+
+#[test]
+fn normal_moves() {
+    let (host, guest) = run_script(&["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6"]);
+    assert_same_board(&host, &guest);
+    assert!(piece_at(&host, "f3") == Some(('N', Colour::White)));
+    assert!(piece_at(&host, "c6") == Some(('N', Colour::Black)));
+    assert!(host.get_active_colour() == Colour::White);
+}
+
+#[test]
+fn castling() {
+    let (host, guest) = run_script(&["e2e4", "e7e5", "g1f3", "g8f6", "f1c4", "f8c5", "e1g1", "e8g8"]);
+    assert_same_board(&host, &guest);
+    assert!(piece_at(&host, "g1") == Some(('K', Colour::White)));
+    assert!(piece_at(&host, "f1") == Some(('R', Colour::White)));
+    assert!(piece_at(&host, "g8") == Some(('K', Colour::Black)));
+    assert!(piece_at(&host, "f8") == Some(('R', Colour::Black)));
+}
+
+#[test]
+fn en_passant() {
+    let (host, guest) = run_script(&["e2e4", "a7a6", "e4e5", "d7d5", "e5d6"]);
+    assert_same_board(&host, &guest);
+    assert!(piece_at(&host, "d6") == Some(('P', Colour::White)));
+    assert!(piece_at(&host, "d5").is_none(), "captured pawn must be removed");
+}
+
+#[test]
+fn promotion_is_sent_on_the_wire() {
+    let (mut host, mut mock) = host_with_mock_client();
+    for (ply, uci) in ["h2h4", "g7g5", "h4g5", "h7h6", "g5h6", "f8g7", "h6g7", "g8f6", "g7h8n"].iter().enumerate() {
+        if ply % 2 == 0 {
+            host.play(uci);
+            mock.expect_move(uci);
+            assert!(host.acked().ok);
+        } else {
+            mock.send_move(uci, false);
+            host.answer();
+            assert!(mock.acked().ok, "host rejected {uci}");
+        }
+    }
+    assert_same_board(host.controller.board(), &mock.game);
+    assert!(piece_at(host.controller.board(), "h8") == Some(('N', Colour::White)));
+}
+
+#[test]
+fn checkmate_is_reported_in_ack() {
+    let (mut host, mut guest) = new_game();
+    let acks = play_moves(&mut host, &mut guest, &["f2f3", "e7e5", "g2g4", "d8h4"]);
+    assert!(acks[1].end_state.is_none());
+    assert!(matches!(acks[3].end_state, Some(GameState::CheckMate)));
+    assert!(host.controller.board().get_game_state() == chess_lib::GameState::GameOver);
+    assert!(matches!(host.controller.state(), UiState::GameOver { reason: EndReason::Board }));
+    assert!(matches!(guest.controller.state(), UiState::GameOver { reason: EndReason::Board }));
+}
+
+#[test]
+fn draw_offer_is_held_until_answered() {
+    let (mut host, mut mock) = host_with_mock_client();
+    host.play("e2e4");
+    mock.expect_move("e2e4");
+    host.acked();
+    mock.send_move("e7e5", true);
+    host.answer();
+    assert!(matches!(host.controller.state(), UiState::DrawOffered));
+    let command = host.controller.handle_input(Input::DrawOfferAnswered(true));
+    host.run(command);
+    let ack = mock.acked();
+    assert!(ack.ok);
+    assert!(matches!(ack.end_state, Some(GameState::Draw)));
+    assert!(matches!(host.controller.state(), UiState::GameOver { reason: EndReason::DrawAgreed }));
+}
+
+#[test]
+fn draw_offer_round_trip() {
+    let (mut host, mut guest) = new_game();
+    play_moves(&mut host, &mut guest, &["e2e4", "e7e5"]);
+    assert!(host.controller.handle_input(Input::DrawRequested).is_none());
+    host.play("g1f3");
+    guest.answer();
+    assert!(matches!(guest.controller.state(), UiState::DrawOffered));
+    let command = guest.controller.handle_input(Input::DrawOfferAnswered(true));
+    guest.run(command);
+    let ack = host.acked();
+    assert!(matches!(ack.end_state, Some(GameState::Draw)));
+    assert!(matches!(host.controller.state(), UiState::GameOver { reason: EndReason::DrawAgreed }));
+    assert!(matches!(guest.controller.state(), UiState::GameOver { reason: EndReason::DrawAgreed }));
+    assert_same_board(host.controller.board(), guest.controller.board());
+}
+
+#[test]
+fn forfeit_is_acked_as_resigned() {
+    let (mut host, mut mock) = host_with_mock_client();
+    host.play("e2e4");
+    mock.expect_move("e2e4");
+    host.acked();
+    mock.send(chess_networking::Move { from: (0, 0), to: (0, 0), forfeit: true, offer_draw: false, promotion: None });
+    host.answer();
+    let (ack, _): (Ack, _) = mock.recv();
+    assert!(matches!(ack.end_state, Some(GameState::Resign)));
+    assert!(matches!(host.controller.state(), UiState::GameOver { reason: EndReason::OpponentForfeited }));
+}
+
+#[test]
+fn illegal_move_is_rejected_and_not_applied() {
+    let (mut host, mut mock) = host_with_mock_client();
+    host.play("e2e4");
+    mock.expect_move("e2e4");
+    host.acked();
+    // A rook jumping over its own pawn.
+    let (from, to) = (position("a8"), position("a5"));
+    mock.send(chess_networking::Move {
+        from: (from.file as u8, from.rank as u8),
+        to: (to.file as u8, to.rank as u8),
+        forfeit: false,
+        offer_draw: false,
+        promotion: None,
+    });
+    host.answer();
+    let (ack, _): (Ack, _) = mock.recv();
+    assert!(!ack.ok);
+    let board = host.controller.board();
+    assert!(board.get_active_colour() == Colour::Black, "rejected move must not change the turn");
+    assert!(piece_at(board, "a8") == Some(('R', Colour::Black)));
+}
+
+#[test]
+fn disconnect_is_an_error_not_a_hang() {
+    let (host, mock) = host_with_mock_client();
+    drop(mock);
+    poll_until(&host.connection, |event| matches!(event, NetworkEvent::Disconnected(_)));
+}
+
+#[test]
+fn disconnect_while_waiting_for_ack() {
+    let (mut host, mut mock) = host_with_mock_client();
+    host.play("e2e4");
+    let _: (chess_networking::Move, _) = mock.recv();
+    drop(mock);
+    poll_until(&host.connection, |event| matches!(event, NetworkEvent::Disconnected(_)));
+    assert!(matches!(host.controller.state(), UiState::WaitingForOpponent));
+    assert!(piece_at(host.controller.board(), "e2") == Some(('P', Colour::White)), "the move was never acked");
+}
+
+#[test]
+fn takeback_is_announced_and_negotiated() {
+    let port = free_port();
+    let connection = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, DEFAULT_TIMEOUT);
+    let mut mock = MockPeer::new(connect_with_retry(&format!("127.0.0.1:{port}")));
+    let _: (Start, _) = mock.recv();
+    let (announced, _): (Extensions, _) = mock.recv();
+    assert!(announced.extensions.iter().any(|name| name == TAKEBACK));
+    write_extension(&mut mock.stream, &Extensions { extensions: vec![TAKEBACK.into()] }).unwrap();
+    write_extension(&mut mock.stream, &TakebackRequest { takeback_to: 0 }).unwrap();
+    let events = poll_until(&connection, |event| matches!(event, NetworkEvent::TakebackRequested(_)));
+    assert!(events.iter().any(|event| matches!(event, NetworkEvent::Extensions(names) if names == &vec![TAKEBACK.to_string()])));
+    assert!(matches!(events.last(), Some(NetworkEvent::TakebackRequested(0))));
+    connection.answer_takeback(0, false);
+    let (answer, _): (TakebackAnswer, _) = mock.recv();
+    assert_eq!(answer, TakebackAnswer { takeback_to: 0, accepted: false });
+}
+
+#[test]
+fn extension_messages_are_skipped_by_plain_peers() {
+    let mut bytes = Vec::new();
+    write_extension(&mut bytes, &Extensions { extensions: vec![TAKEBACK.into()] }).unwrap();
+    write_extension(&mut bytes, &TakebackRequest { takeback_to: 3 }).unwrap();
+    bytes.extend(encode(to_network_move(&uci_move(&Game::new(), "e2e4"), false)));
+    let received: chess_networking::Move = read_message(&mut bytes.as_slice()).unwrap();
+    assert_eq!((received.from, received.to), ((4, 1), (4, 3)));
+}
+
+#[test]
+fn takeback_answers_are_not_read_as_requests() {
+    let mut bytes = Vec::new();
+    write_extension(&mut bytes, &TakebackAnswer { takeback_to: 2, accepted: true }).unwrap();
+    write_extension(&mut bytes, &TakebackRequest { takeback_to: 1 }).unwrap();
+    let request: TakebackRequest = read_message(&mut bytes.as_slice()).unwrap();
+    assert_eq!(request, TakebackRequest { takeback_to: 1 });
+}
+
+#[test]
+fn rehosted_game_exchanges_positions() {
+    let port = free_port();
+    let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1";
+    let connection = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), Some(fen.into()), DEFAULT_TIMEOUT);
+    let mut mock = MockPeer::new(connect_with_retry(&format!("127.0.0.1:{port}")));
+    let _: (Start, _) = mock.recv();
+    let (announced, _): (Extensions, _) = mock.recv();
+    assert!(announced.extensions.iter().any(|name| name == RESYNC));
+    write_extension(&mut mock.stream, &Extensions { extensions: vec![RESYNC.into()] }).unwrap();
+    write_extension(&mut mock.stream, &Resync { fen: fen.into(), ply: 1 }).unwrap();
+    let events = poll_until(&connection, |event| matches!(event, NetworkEvent::Resync { .. }));
+    assert!(matches!(events.last(), Some(NetworkEvent::Resync { ply: 1, .. })));
+    connection.resync(fen.into(), 1);
+    let (resync, _): (Resync, _) = mock.recv();
+    assert_eq!(resync, Resync { fen: fen.into(), ply: 1 });
+}
+
+#[test]
+fn silent_heartbeat_peer_times_out() {
+    let port = free_port();
+    let connection = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, Duration::from_secs(1));
+    let mut mock = MockPeer::new(connect_with_retry(&format!("127.0.0.1:{port}")));
+    let _: (Start, _) = mock.recv();
+    let (announced, _): (Extensions, _) = mock.recv();
+    assert!(announced.extensions.iter().any(|name| name == HEARTBEAT));
+    write_extension(&mut mock.stream, &Extensions { extensions: vec![HEARTBEAT.into()] }).unwrap();
+    let (_, _): (Heartbeat, _) = mock.recv();
+    let events = poll_until(&connection, |event| matches!(event, NetworkEvent::Disconnected(_)));
+    assert!(matches!(events.last(), Some(NetworkEvent::Disconnected(reason)) if reason.starts_with("Nothing heard")));
+}
+
+#[test]
+fn join_addresses_take_names_and_bracketed_ipv6() {
+    assert_eq!(split_host_port(" example.com:8080 "), Ok((String::from("example.com"), 8080)));
+    assert_eq!(split_host_port("192.168.1.5:91"), Ok((String::from("192.168.1.5"), 91)));
+    assert_eq!(split_host_port("[::1]:8080"), Ok((String::from("::1"), 8080)));
+    assert!(split_host_port("::1:8080").is_err());
+    assert!(split_host_port("[::1]").is_err());
+    assert!(split_host_port("example.com").is_err());
+    assert!(split_host_port(":8080").is_err());
+    assert!(split_host_port("example.com:99999").is_err());
+}
+
+#[test]
+fn hosts_bound_to_one_address_share_just_that() {
+    assert_eq!(shareable_addresses("192.168.1.5", 8080), vec!["192.168.1.5:8080"]);
+    assert_eq!(shareable_addresses("[::1]", 8080), vec!["[::1]:8080"]);
+    assert_eq!(shareable_addresses("example.com", 8080), vec!["example.com:8080"]);
+    assert!(shareable_addresses("0.0.0.0", 8080).iter().all(|address| !address.starts_with('[') && !address.starts_with("127.")));
+}
+
+#[test]
+fn ipv6_host_can_be_joined() {
+    // Not every machine running the tests has IPv6.
+    let Ok(listener) = TcpListener::bind("[::1]:0") else {
+        return;
+    };
+    let port = listener.local_addr().unwrap().port();
+    drop(listener);
+    let host = thread::spawn(move || start_server("[::1]", &port.to_string(), "host"));
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let (_stream, name, _, _) = loop {
+        match start_client(&format!("[::1]:{port}"), "guest") {
+            Ok(connected) => break connected,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(10)),
+            Err(err) => panic!("could not join: {err}"),
+        }
+    };
+    assert_eq!(name.as_deref(), Some("host"));
+    assert!(host.join().unwrap().is_ok());
+}
+
+#[test]
+fn spectators_follow_a_hosted_game() {
+    let port = free_port();
+    let connection = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, DEFAULT_TIMEOUT);
+    // The first spectator turns up before the opponent and is not taken for it.
+    thread::sleep(Duration::from_millis(100));
+    let early = Connection::watch(format!("127.0.0.1:{port}"), "early".into());
+    thread::sleep(Duration::from_millis(100));
+    let mut mock = MockPeer::new(connect_with_retry(&format!("127.0.0.1:{port}")));
+    let _: (Start, _) = mock.recv();
+    poll_until(&connection, |event| matches!(event, NetworkEvent::Connected { .. }));
+    let broadcast = Broadcast { white: Some("host".into()), black: None, start: INITIAL.into(), moves: vec!["e2e4".into()], end: None };
+    connection.broadcast(broadcast.clone());
+    let events = poll_until(&early, |event| matches!(event, NetworkEvent::Broadcast(_)));
+    assert!(matches!(events.last(), Some(NetworkEvent::Broadcast(received)) if *received == broadcast));
+    // Later ones start from the latest update.
+    let late = Connection::watch(format!("127.0.0.1:{port}"), "late".into());
+    let events = poll_until(&late, |event| matches!(event, NetworkEvent::Broadcast(_)));
+    assert!(matches!(events.last(), Some(NetworkEvent::Broadcast(received)) if *received == broadcast));
+    drop(connection);
+    poll_until(&late, |event| matches!(event, NetworkEvent::Disconnected(_)));
+}
+
+#[test]
+fn chat_goes_both_ways() {
+    let port = free_port();
+    let connection = Connection::host("127.0.0.1".into(), port.to_string(), "host".into(), None, DEFAULT_TIMEOUT);
+    let mut mock = MockPeer::new(connect_with_retry(&format!("127.0.0.1:{port}")));
+    let _: (Start, _) = mock.recv();
+    let (announced, _): (Extensions, _) = mock.recv();
+    assert!(announced.extensions.iter().any(|name| name == CHAT));
+    write_extension(&mut mock.stream, &Extensions { extensions: vec![CHAT.into()] }).unwrap();
+    write_extension(&mut mock.stream, &Chat { text: "good luck".into() }).unwrap();
+    let events = poll_until(&connection, |event| matches!(event, NetworkEvent::Chat(_)));
+    assert!(matches!(events.last(), Some(NetworkEvent::Chat(text)) if text == "good luck"));
+    connection.send_chat("you too".into());
+    let (reply, _): (Chat, _) = mock.recv();
+    assert_eq!(reply, Chat { text: "you too".into() });
+}